@@ -0,0 +1,296 @@
+use std::io;
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::acl::IpRules;
+use crate::config::config_dir;
+use crate::guests::GuestStore;
+use crate::permissions::{PathRules, Permission, Permissions};
+use crate::quota::Quota;
+
+/// 账户文件名，位于配置目录下
+pub const ACCOUNTS_FILE: &str = "accounts.json";
+
+/// 账户可进行的操作，也用作整个共享的模式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    #[default]
+    ReadWrite,
+    /// 只能下载
+    ReadOnly,
+    /// 只能上传
+    UploadOnly,
+}
+
+impl Access {
+    /// 没有路径规则时授予的权限
+    pub fn permissions(self) -> Permissions {
+        match self {
+            Access::ReadWrite => Permissions::ALL,
+            Access::ReadOnly => Permissions::of(&[Permission::List, Permission::Download]),
+            // 仅上传时客户端看不到已有文件
+            Access::UploadOnly => {
+                Permissions::of(&[Permission::Upload, Permission::Overwrite, Permission::Mkdir])
+            }
+        }
+    }
+}
+
+/// 登录账户
+///
+/// 除界面上填写的用户外，还可以在 `config/accounts.json` 中配置多个账户：
+///
+/// ```json
+/// [
+///   { "username": "alice", "password": "secret", "home": "alice", "quota_bytes": 1073741824, "quota_files": 1000 }
+/// ]
+/// ```
+///
+/// `access` 可设为 `read_only` 或 `upload_only`，默认可读写；
+/// `ip_rules` 限制可登录的地址，格式见 [`IpRules`]；
+/// `permissions` 按路径细分权限，格式见 [`PathRules`]。
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Account {
+    pub username: String,
+    pub password: String,
+    /// 相对于共享根目录的主目录，为空表示整个共享
+    pub home: Option<String>,
+    pub quota_bytes: Option<u64>,
+    pub quota_files: Option<u64>,
+    pub access: Access,
+    pub ip_rules: IpRules,
+    pub permissions: PathRules,
+    /// 匿名模式下登录的账户
+    #[serde(skip)]
+    pub anonymous: bool,
+}
+
+impl Account {
+    /// 匿名模式下的账户，可访问整个共享且不受配额限制
+    pub fn anonymous(username: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            anonymous: true,
+            ..Default::default()
+        }
+    }
+
+    /// 账户的 IP 规则是否允许从该地址登录，拒绝时记录日志
+    pub fn permits_ip(&self, ip: IpAddr) -> bool {
+        let permitted = self.ip_rules.permits(ip);
+        if !permitted {
+            warn!(
+                "Refused login of {} from {}: blocked by account IP rules",
+                self.username, ip
+            );
+        }
+        permitted
+    }
+
+    /// 账户在该路径上拥有的权限，路径相对于主目录
+    pub fn permissions_at(&self, path: &Path) -> Permissions {
+        self.permissions.resolve(self.access, path)
+    }
+
+    pub fn quota(&self) -> Quota {
+        Quota {
+            max_bytes: self.quota_bytes,
+            max_files: self.quota_files,
+        }
+    }
+
+    /// 账户主目录的实际路径，不存在时自动创建
+    pub fn home_dir(&self, root: &Path) -> io::Result<PathBuf> {
+        let Some(home) = self.home.as_deref().filter(|home| !home.trim().is_empty()) else {
+            return Ok(root.to_path_buf());
+        };
+
+        // 共享选中的文件时没有实际的根目录
+        if root.as_os_str().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "home directory of {} requires a shared directory",
+                    self.username
+                ),
+            ));
+        }
+
+        let relative = Path::new(home.trim_start_matches(['/', '\\']));
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("invalid home directory for {}: {}", self.username, home),
+            ));
+        }
+
+        let dir = root.join(relative);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+}
+
+/// 所有可登录账户，包括界面上创建的临时访客账户
+#[derive(Debug)]
+pub struct AccountStore {
+    accounts: Vec<Account>,
+    guests: Arc<GuestStore>,
+    /// 账户文件有误时拒绝所有登录，避免静默退化为匿名模式
+    deny_all: bool,
+}
+
+impl AccountStore {
+    /// 合并界面填写的账户与配置文件中的账户，两者都为空时为匿名模式
+    pub fn load(username: &str, password: &str, guests: Arc<GuestStore>) -> Self {
+        let mut accounts = Vec::new();
+        if !username.is_empty() && !password.is_empty() {
+            accounts.push(Account {
+                username: username.to_string(),
+                password: password.to_string(),
+                ..Default::default()
+            });
+        }
+
+        let path = config_dir().join(ACCOUNTS_FILE);
+        let mut deny_all = false;
+        match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<Vec<Account>>(&content) {
+                Ok(loaded) => {
+                    info!("Loaded {} account(s) from {:?}", loaded.len(), path);
+                    accounts.extend(loaded);
+                }
+                Err(e) => {
+                    warn!("Failed to parse {:?}, refusing all logins: {}", path, e);
+                    deny_all = true;
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!("Failed to read {:?}, refusing all logins: {}", path, e);
+                deny_all = true;
+            }
+        }
+
+        Self {
+            accounts,
+            guests,
+            deny_all,
+        }
+    }
+
+    /// 没有配置账户时为匿名模式，访客账户仍按自身权限登录
+    pub fn is_anonymous(&self) -> bool {
+        self.accounts.is_empty() && !self.deny_all
+    }
+
+    /// 校验用户名密码，成功时返回对应账户；已过期的访客账户无法登录
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<Account> {
+        if self.deny_all {
+            return None;
+        }
+        // 访客用户名只能以访客身份登录，密码错误或访客已失效时不退回匿名登录
+        if self.guests.contains(username) {
+            return self.guests.authenticate(username, password);
+        }
+        if self.is_anonymous() {
+            return Some(Account::anonymous(username));
+        }
+        self.accounts
+            .iter()
+            .find(|account| account.username == username && account.password == password)
+            .cloned()
+    }
+
+    /// 不校验密码的登录，例如 SFTP 公钥认证，只在匿名模式下允许，访客账户仍须使用密码
+    pub fn login_without_password(&self, username: &str) -> Option<Account> {
+        if !self.is_anonymous() || self.guests.contains(username) {
+            return None;
+        }
+        Some(Account::anonymous(username))
+    }
+
+    pub fn get(&self, username: &str) -> Option<Account> {
+        if self.deny_all {
+            return None;
+        }
+        if self.guests.contains(username) {
+            return self.guests.get(username);
+        }
+        if self.is_anonymous() {
+            return Some(Account::anonymous(username));
+        }
+        self.accounts
+            .iter()
+            .find(|account| account.username == username)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(accounts: Vec<Account>, guests: Arc<GuestStore>) -> AccountStore {
+        AccountStore {
+            accounts,
+            guests,
+            deny_all: false,
+        }
+    }
+
+    #[test]
+    fn anonymous_mode_rejects_guest_with_wrong_password() {
+        let guests = Arc::new(GuestStore::new());
+        let guest = guests.create(None, Access::ReadOnly, None, None);
+        let username = &guest.account.username;
+        let store = store(Vec::new(), guests.clone());
+        assert!(store.is_anonymous());
+
+        let account = store
+            .authenticate(username, &guest.account.password)
+            .unwrap();
+        assert!(!account.anonymous);
+        assert_eq!(account.access, Access::ReadOnly);
+        assert!(store.authenticate(username, "wrong").is_none());
+        assert!(store.login_without_password(username).is_none());
+        assert!(store.authenticate("someone", "anything").unwrap().anonymous);
+    }
+
+    #[test]
+    fn removed_guest_cannot_log_in_anonymously() {
+        let guests = Arc::new(GuestStore::new());
+        let guest = guests.create(None, Access::ReadWrite, None, None);
+        let username = &guest.account.username;
+        let store = store(Vec::new(), guests.clone());
+        guests.remove(username);
+        assert!(
+            store
+                .authenticate(username, &guest.account.password)
+                .is_none()
+        );
+        assert!(store.get(username).is_none());
+    }
+
+    #[test]
+    fn configured_accounts_require_password() {
+        let account = Account {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+            ..Default::default()
+        };
+        let store = store(vec![account], Arc::new(GuestStore::new()));
+        assert!(!store.is_anonymous());
+        assert!(store.authenticate("alice", "secret").is_some());
+        assert!(store.authenticate("alice", "wrong").is_none());
+        assert!(store.authenticate("bob", "secret").is_none());
+    }
+}
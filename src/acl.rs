@@ -0,0 +1,232 @@
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::config::config_dir;
+
+/// 全局 IP 规则文件名，位于配置目录下
+pub const IP_RULES_FILE: &str = "ip_rules.json";
+
+/// CIDR 网段，例如 `192.168.1.0/24`，省略前缀长度时表示单个地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| format!("invalid address in {:?}", s))?
+            .to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length in {:?}", s))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// IP 允许/拒绝列表，拒绝优先；允许列表为空时允许所有未被拒绝的地址
+///
+/// 全局规则在 `config/ip_rules.json` 中配置，连接建立时检查：
+///
+/// ```json
+/// { "allow": ["192.168.0.0/16", "fd00::/8"], "deny": ["192.168.1.13"] }
+/// ```
+///
+/// 账户的 `ip_rules` 字段格式相同，在登录时检查。
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IpRules {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl IpRules {
+    pub fn load() -> Self {
+        let path = config_dir().join(IP_RULES_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<IpRules>(&content) {
+                Ok(rules) => {
+                    info!(
+                        "Loaded IP rules from {:?}: {} allow, {} deny",
+                        path,
+                        rules.allow.len(),
+                        rules.deny.len()
+                    );
+                    rules
+                }
+                Err(e) => {
+                    // 规则文件有误时拒绝所有连接，避免访问控制静默失效
+                    warn!(
+                        "Failed to parse {:?}, refusing all connections: {}",
+                        path, e
+                    );
+                    Self::deny_all()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                warn!("Failed to read {:?}, refusing all connections: {}", path, e);
+                Self::deny_all()
+            }
+        }
+    }
+
+    fn deny_all() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: vec![
+                Cidr {
+                    addr: IpAddr::from([0, 0, 0, 0]),
+                    prefix: 0,
+                },
+                Cidr {
+                    addr: IpAddr::from([0u16; 8]),
+                    prefix: 0,
+                },
+            ],
+        }
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|cidr| cidr.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn rules(json: &str) -> IpRules {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn cidr_matches_prefix() {
+        let net = cidr("192.168.1.0/24");
+        assert!(net.contains(ip("192.168.1.0")));
+        assert!(net.contains(ip("192.168.1.255")));
+        assert!(!net.contains(ip("192.168.2.1")));
+        assert!(!net.contains(ip("fe80::1")));
+
+        let net = cidr("fd00::/8");
+        assert!(net.contains(ip("fd12:3456::1")));
+        assert!(!net.contains(ip("fe80::1")));
+        assert!(!net.contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn cidr_prefix_bounds() {
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.7")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+
+        let host = cidr("10.0.0.1/32");
+        assert_eq!(host, cidr("10.0.0.1"));
+        assert!(host.contains(ip("10.0.0.1")));
+        assert!(!host.contains(ip("10.0.0.2")));
+
+        let host = cidr("2001:db8::1/128");
+        assert_eq!(host, cidr("2001:db8::1"));
+        assert!(host.contains(ip("2001:db8::1")));
+        assert!(!host.contains(ip("2001:db8::2")));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+        assert!("example.com/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_match_ipv4_rules() {
+        // 双栈监听时 IPv4 客户端的地址为 `::ffff:a.b.c.d`
+        let net = cidr("192.168.1.0/24");
+        assert!(net.contains(ip("::ffff:192.168.1.20")));
+        assert!(!net.contains(ip("::ffff:192.168.2.20")));
+        // 规则本身写成映射地址时按 IPv4 解析
+        assert_eq!(cidr("::ffff:192.168.1.20"), cidr("192.168.1.20"));
+        assert!(cidr("::ffff:192.168.1.20").contains(ip("192.168.1.20")));
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let rules = rules(r#"{ "allow": ["192.168.0.0/16"], "deny": ["192.168.1.13"] }"#);
+        assert!(rules.permits(ip("192.168.1.12")));
+        assert!(!rules.permits(ip("192.168.1.13")));
+        assert!(!rules.permits(ip("::ffff:192.168.1.13")));
+        assert!(!rules.permits(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn empty_allow_list_permits_all_not_denied() {
+        let rules = rules(r#"{ "deny": ["10.0.0.0/8"] }"#);
+        assert!(rules.permits(ip("192.168.1.1")));
+        assert!(rules.permits(ip("::1")));
+        assert!(!rules.permits(ip("10.1.2.3")));
+        assert!(IpRules::default().permits(ip("10.1.2.3")));
+    }
+
+    #[test]
+    fn deny_all_rejects_every_address() {
+        let rules = IpRules::deny_all();
+        assert!(!rules.permits(ip("127.0.0.1")));
+        assert!(!rules.permits(ip("::1")));
+        assert!(!rules.permits(ip("::ffff:127.0.0.1")));
+    }
+
+    #[test]
+    fn invalid_rules_fail_to_parse() {
+        assert!(serde_json::from_str::<IpRules>(r#"{ "allow": ["10.0.0.0/40"] }"#).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, NaiveDate};
+use log::warn;
+
+use crate::guests::GuestStore;
+use crate::hooks::{Event, EventKind, Hooks};
+use crate::server::Services;
+use crate::throttle::Direction;
+
+/// 发起传输的一方
+#[derive(Debug, Clone)]
+pub struct TransferSource {
+    /// `ftp`、`sftp` 或 `tftp`
+    pub protocol: &'static str,
+    pub username: String,
+    pub anonymous: bool,
+    pub remote_host: String,
+}
+
+/// 一条传输记录
+#[derive(Debug, Clone)]
+pub struct TransferRecord {
+    pub finished_at: DateTime<Local>,
+    pub duration: Duration,
+    pub source: TransferSource,
+    pub bytes: u64,
+    pub path: String,
+    pub direction: Direction,
+    pub complete: bool,
+}
+
+impl TransferRecord {
+    /// wu-ftpd xferlog 格式：
+    /// `current-time transfer-time remote-host file-size filename transfer-type
+    /// special-action-flag direction access-mode username service-name
+    /// authentication-method authenticated-user-id completion-status`
+    pub fn to_xferlog(&self) -> String {
+        format!(
+            "{} {} {} {} {} b _ {} {} {} {} 0 * {}",
+            self.finished_at.format("%a %b %e %H:%M:%S %Y"),
+            self.duration.as_secs().max(1),
+            self.source.remote_host,
+            self.bytes,
+            self.path.replace(char::is_whitespace, "_"),
+            match self.direction {
+                Direction::Upload => 'i',
+                Direction::Download => 'o',
+            },
+            if self.source.anonymous { 'a' } else { 'r' },
+            if self.source.username.is_empty() {
+                "*"
+            } else {
+                self.source.username.as_str()
+            },
+            self.source.protocol,
+            if self.complete { 'c' } else { 'i' },
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "time": self.finished_at.to_rfc3339(),
+            "duration_ms": self.duration.as_millis() as u64,
+            "remote_host": self.source.remote_host,
+            "bytes": self.bytes,
+            "path": self.path,
+            "direction": match self.direction {
+                Direction::Upload => "upload",
+                Direction::Download => "download",
+            },
+            "username": self.source.username,
+            "anonymous": self.source.anonymous,
+            "protocol": self.source.protocol,
+            "complete": self.complete,
+        })
+        .to_string()
+    }
+}
+
+/// 传输审计日志，每天一个文件：`xferlog-YYYYMMDD`，可选同时写出 `xferlog-YYYYMMDD.jsonl`
+#[derive(Debug)]
+pub struct TransferLog {
+    dir: PathBuf,
+    enabled: AtomicBool,
+    json: AtomicBool,
+    files: Mutex<Option<DailyFiles>>,
+}
+
+#[derive(Debug)]
+struct DailyFiles {
+    date: NaiveDate,
+    xferlog: File,
+    json: Option<File>,
+}
+
+impl TransferLog {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            enabled: AtomicBool::new(true),
+            json: AtomicBool::new(false),
+            files: Mutex::new(None),
+        }
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    pub fn set_options(&self, enabled: bool, json: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        self.json.store(json, Ordering::Relaxed);
+        // 下次写入时按新设置重新打开文件
+        *self.files.lock().unwrap() = None;
+    }
+
+    pub fn record(&self, record: &TransferRecord) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(e) = self.write(record) {
+            warn!("Failed to write transfer log: {}", e);
+        }
+    }
+
+    fn write(&self, record: &TransferRecord) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let date = record.finished_at.date_naive();
+        if files.as_ref().is_none_or(|files| files.date != date) {
+            *files = Some(self.open(date)?);
+        }
+
+        let files = files.as_mut().unwrap();
+        writeln!(files.xferlog, "{}", record.to_xferlog())?;
+        if let Some(json) = files.json.as_mut() {
+            writeln!(json, "{}", record.to_json())?;
+        }
+        Ok(())
+    }
+
+    fn open(&self, date: NaiveDate) -> io::Result<DailyFiles> {
+        fs::create_dir_all(&self.dir)?;
+        let name = format!("xferlog-{}", date.format("%Y%m%d"));
+        let append = |path: PathBuf| OpenOptions::new().create(true).append(true).open(path);
+        let json = if self.json.load(Ordering::Relaxed) {
+            Some(append(self.dir.join(format!("{}.jsonl", name)))?)
+        } else {
+            None
+        };
+        Ok(DailyFiles {
+            date,
+            xferlog: append(self.dir.join(name))?,
+            json,
+        })
+    }
+}
+
+/// 跟踪一次传输，在销毁时写入审计日志，传输完成时触发上传/下载钩子并计入访客下载次数
+#[derive(Debug)]
+pub struct TransferTracker {
+    log: Arc<TransferLog>,
+    hooks: Arc<Hooks>,
+    guests: Arc<GuestStore>,
+    source: TransferSource,
+    path: String,
+    direction: Direction,
+    started: Instant,
+    bytes: u64,
+    complete: bool,
+    /// 是否传输了整个文件，续传和范围请求读到结尾也不计入访客下载次数
+    whole: bool,
+}
+
+impl TransferTracker {
+    pub fn new(
+        services: &Services,
+        source: TransferSource,
+        path: String,
+        direction: Direction,
+    ) -> Self {
+        Self {
+            log: services.transfer_log.clone(),
+            hooks: services.hooks.clone(),
+            guests: services.guests.clone(),
+            source,
+            path,
+            direction,
+            started: Instant::now(),
+            bytes: 0,
+            complete: false,
+            whole: true,
+        }
+    }
+
+    pub fn add(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+
+    pub fn set_complete(&mut self) {
+        self.complete = true;
+    }
+
+    /// 只传输文件的一部分
+    pub fn set_partial(&mut self) {
+        self.whole = false;
+    }
+
+    /// 在 `offset` 处读取，尚未传输任何数据就从中间开始说明不是完整下载
+    pub fn read_from(&mut self, offset: u64) {
+        if self.bytes == 0 && offset > 0 {
+            self.whole = false;
+        }
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+}
+
+impl Drop for TransferTracker {
+    fn drop(&mut self) {
+        let record = TransferRecord {
+            finished_at: Local::now(),
+            duration: self.started.elapsed(),
+            source: self.source.clone(),
+            bytes: self.bytes,
+            path: std::mem::take(&mut self.path),
+            direction: self.direction,
+            complete: self.complete,
+        };
+        self.log.record(&record);
+
+        if record.complete {
+            let kind = match record.direction {
+                Direction::Upload => EventKind::Upload,
+                Direction::Download => {
+                    if self.whole {
+                        self.guests.record_download(&record.source.username);
+                    }
+                    EventKind::Download
+                }
+            };
+            self.hooks.fire(
+                Event::new(kind, record.source)
+                    .with_path(record.path)
+                    .with_bytes(record.bytes),
+            );
+        }
+    }
+}
+
+/// 统计经过的字节数：读到结尾或写入端关闭时视为传输完成
+pub struct Audited<T> {
+    inner: T,
+    tracker: TransferTracker,
+}
+
+impl<T> Audited<T> {
+    pub fn new(inner: T, tracker: TransferTracker) -> Self {
+        Self { inner, tracker }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Audited<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = buf.filled().len() - before;
+        if read == 0 && buf.remaining() > 0 {
+            this.tracker.set_complete();
+        }
+        this.tracker.add(read as u64);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// 上传写入存储时使用：数据全部写入并成功关闭后才算完成，写入失败或被拒绝时记为未完成
+impl<T: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for Audited<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.tracker.add(n as u64);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.inner).poll_shutdown(cx))?;
+        this.tracker.set_complete();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: futures_lite::AsyncRead + Unpin> futures_lite::AsyncRead for Audited<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if n == 0 && !buf.is_empty() {
+            this.tracker.set_complete();
+        }
+        this.tracker.add(n as u64);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: futures_lite::AsyncWrite + Unpin> futures_lite::AsyncWrite for Audited<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.tracker.add(n as u64);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.inner).poll_close(cx))?;
+        this.tracker.set_complete();
+        Poll::Ready(Ok(()))
+    }
+}
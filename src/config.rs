@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+/// 配置目录：优先使用环境变量 `FTP_QUICK_CONFIG_DIR`，否则为程序所在目录下的 `config`
+pub fn config_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("FTP_QUICK_CONFIG_DIR") {
+        return PathBuf::from(dir);
+    }
+    exe_dir().join("config")
+}
+
+/// 日志目录：优先使用环境变量 `FTP_QUICK_LOG_DIR`，否则为程序所在目录下的 `logs`
+pub fn log_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("FTP_QUICK_LOG_DIR") {
+        return PathBuf::from(dir);
+    }
+    exe_dir().join("logs")
+}
+
+fn exe_dir() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
@@ -0,0 +1,109 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use log::warn;
+use unftp_core::auth::{AuthenticationError, Authenticator, Credentials, Principal};
+
+use crate::accounts::AccountStore;
+use crate::audit::TransferSource;
+use crate::hooks::{Event, EventKind};
+use crate::limits::UserGuard;
+use crate::lockout::MAX_AUTH_ATTEMPTS;
+use crate::server::Services;
+
+/// 基于 `AccountStore` 的 FTP 认证，经策略脚本放行后登录，成功或失败时触发钩子
+///
+/// 每个控制连接一个实例，用于限制单个连接的认证次数。
+#[derive(Debug)]
+pub struct AccountAuthenticator {
+    accounts: Arc<AccountStore>,
+    services: Services,
+    peer: SocketAddr,
+    attempts: AtomicU32,
+    /// 登录后占用的单用户连接数，随连接关闭释放
+    user_guard: Mutex<Option<UserGuard>>,
+}
+
+impl AccountAuthenticator {
+    pub fn new(accounts: Arc<AccountStore>, services: Services, peer: SocketAddr) -> Self {
+        Self {
+            accounts,
+            services,
+            peer,
+            attempts: AtomicU32::new(0),
+            user_guard: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for AccountAuthenticator {
+    async fn authenticate(
+        &self,
+        username: &str,
+        creds: &Credentials,
+    ) -> Result<Principal, AuthenticationError> {
+        let password = creds.password.as_deref().unwrap_or("");
+        let remote_host = self.peer.ip().to_string();
+        let lockouts = &self.services.lockouts;
+        if self.attempts.fetch_add(1, Ordering::Relaxed) >= MAX_AUTH_ATTEMPTS {
+            warn!(
+                "Too many login attempts on FTP connection from {}",
+                self.peer
+            );
+            return Err(AuthenticationError::BadPassword);
+        }
+        if lockouts.is_locked(self.peer.ip(), username) {
+            warn!(
+                "Rejected FTP login of {} from {}: locked out",
+                username, self.peer
+            );
+            return Err(AuthenticationError::BadPassword);
+        }
+
+        let account = self.accounts.authenticate(username, password);
+        if account.is_some() {
+            lockouts.record_success(username);
+        } else {
+            lockouts.record_failure(self.peer.ip(), username);
+        }
+        let account = account
+            .filter(|account| account.permits_ip(self.peer.ip()))
+            .filter(|_| {
+                self.services
+                    .policy
+                    .authorize(username, &remote_host, "ftp")
+            });
+        // 同一连接重新登录时先释放之前占用的计数
+        self.user_guard.lock().unwrap().take();
+        let user_guard = account
+            .as_ref()
+            .and_then(|account| self.services.connections.login(&account.username));
+        let account = account.filter(|_| user_guard.is_some());
+        let source = TransferSource {
+            protocol: "ftp",
+            username: username.to_string(),
+            anonymous: account.as_ref().is_some_and(|account| account.anonymous),
+            remote_host,
+        };
+        match account {
+            Some(account) => {
+                *self.user_guard.lock().unwrap() = user_guard;
+                self.services
+                    .hooks
+                    .fire(Event::new(EventKind::Login, source));
+                Ok(Principal {
+                    username: account.username,
+                })
+            }
+            None => {
+                self.services
+                    .hooks
+                    .fire(Event::new(EventKind::LoginFailed, source));
+                Err(AuthenticationError::BadPassword)
+            }
+        }
+    }
+}
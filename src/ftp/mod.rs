@@ -0,0 +1,7 @@
+pub mod auth;
+pub mod storage;
+pub mod user;
+
+pub use auth::AccountAuthenticator;
+pub use storage::FtpStorage;
+pub use user::{FtpUser, FtpUserProvider};
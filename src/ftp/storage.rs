@@ -0,0 +1,392 @@
+use std::fmt::Debug;
+use std::io::{self, SeekFrom};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use log::warn;
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt, ReadBuf};
+use unftp_core::storage::{
+    self, Error, ErrorKind, FEATURE_RESTART, Fileinfo, Result, StorageBackend,
+};
+
+use crate::audit::{Audited, TransferSource, TransferTracker};
+use crate::ftp::FtpUser;
+use crate::policy::{Decision, UploadCheck};
+use crate::server::Services;
+use crate::throttle::{Direction, Throttled, TransferThrottle};
+use crate::vfs::{self, AccountFs, FileKind, LocalFs, Metadata, OpenOptions, Vfs};
+
+/// 基于 [`Vfs`] 的存储后端，与 SFTP 共用同一套权限和配额检查，并在传输路径上施加限速和记录传输日志
+#[derive(Debug)]
+pub struct FtpStorage {
+    root: PathBuf,
+    transfer: TransferThrottle,
+    services: Services,
+    peer: SocketAddr,
+    /// 登录后构建的存储，整个会话共用
+    session: Option<Session>,
+}
+
+/// 已登录用户的存储
+#[derive(Debug)]
+struct Session {
+    /// 用户主目录下的内容，不做权限检查；能访问整个共享的用户还能看到挂载点
+    home: Arc<dyn Vfs>,
+    /// 以用户身份访问的存储
+    fs: AccountFs,
+}
+
+impl FtpStorage {
+    pub fn new(root: PathBuf, services: &Services, peer: SocketAddr) -> Self {
+        Self {
+            root,
+            transfer: services.throttle.session(""),
+            services: services.clone(),
+            peer,
+            session: None,
+        }
+    }
+
+    fn source(&self, user: &FtpUser) -> TransferSource {
+        TransferSource {
+            protocol: "ftp",
+            username: user.username.clone(),
+            anonymous: user.anonymous,
+            remote_host: self.peer.ip().to_string(),
+        }
+    }
+
+    /// 登录时构建该用户的存储，之后的命令都使用它
+    fn open_session(&self, user: &FtpUser) -> Session {
+        let home: Arc<dyn Vfs> = match &user.home {
+            Some(home) => Arc::new(LocalFs::new(home.clone())),
+            None => vfs::share(self.root.clone(), &self.services, &user.username),
+        };
+        let fs = AccountFs::new(
+            home.clone(),
+            &self.services,
+            self.source(user),
+            user.access,
+            user.permissions.clone(),
+        )
+        .with_quota(user.quota);
+        Session { home, fs }
+    }
+
+    fn fs(&self) -> Result<&AccountFs> {
+        Ok(&self.entered()?.fs)
+    }
+
+    fn entered(&self) -> Result<&Session> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| Error::from(ErrorKind::PermissionDenied))
+    }
+
+    fn track(
+        &self,
+        user: &FtpUser,
+        fs: &AccountFs,
+        path: &Path,
+        direction: Direction,
+    ) -> TransferTracker {
+        TransferTracker::new(
+            &self.services,
+            self.source(user),
+            fs.display_path(path),
+            direction,
+        )
+    }
+}
+
+/// 应用策略脚本的裁决，返回可能被改写的路径
+fn apply_policy(user: &FtpUser, path: PathBuf, decision: Decision) -> Result<PathBuf> {
+    match decision {
+        Decision::Allow => Ok(path),
+        Decision::Rewrite(rewritten) => Ok(PathBuf::from(rewritten)),
+        Decision::Deny => {
+            warn!("{}: {:?} denied by policy", user, path);
+            Err(Error::from(ErrorKind::PermissionDenied))
+        }
+    }
+}
+
+/// 上传过程中按已写入的字节数再次询问策略脚本，拒绝或改写到其他路径时中断上传
+struct PolicyChecked<R> {
+    inner: R,
+    check: UploadCheck,
+    size: u64,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for PolicyChecked<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = buf.filled().len() - before;
+        let allowed = if read == 0 {
+            this.check.finish()
+        } else {
+            this.size += read as u64;
+            this.check.wrote(this.size)
+        };
+        if !allowed {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Denied by policy",
+            )));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// 将存储错误转换为 FTP 应答，配额错误对应 552
+fn storage_error(e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::StorageFull => Error::new(ErrorKind::ExceededStorageAllocationError, e),
+        io::ErrorKind::DirectoryNotEmpty => Error::new(ErrorKind::PermanentDirectoryNotEmpty, e),
+        io::ErrorKind::NotADirectory => Error::new(ErrorKind::PermanentDirectoryNotAvailable, e),
+        _ => Error::from(e),
+    }
+}
+
+impl storage::Metadata for Metadata {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn is_dir(&self) -> bool {
+        Metadata::is_dir(self)
+    }
+
+    fn is_file(&self) -> bool {
+        Metadata::is_file(self)
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        self.modified
+            .ok_or_else(|| Error::from(ErrorKind::PermanentFileNotAvailable))
+    }
+
+    fn gid(&self) -> u32 {
+        self.gid.unwrap_or(0)
+    }
+
+    fn uid(&self) -> u32 {
+        self.uid.unwrap_or(0)
+    }
+
+    fn links(&self) -> u64 {
+        self.nlink.unwrap_or(1)
+    }
+
+    fn permissions(&self) -> storage::Permissions {
+        storage::Permissions(self.mode.unwrap_or(0o755))
+    }
+}
+
+#[async_trait]
+impl StorageBackend<FtpUser> for FtpStorage {
+    type Metadata = Metadata;
+
+    fn enter(&mut self, user: &FtpUser) -> io::Result<()> {
+        self.transfer.bind_user(&user.username);
+        self.session = Some(self.open_session(user));
+        Ok(())
+    }
+
+    fn supported_features(&self) -> u32 {
+        FEATURE_RESTART
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(
+        &self,
+        _user: &FtpUser,
+        path: P,
+    ) -> Result<Self::Metadata> {
+        self.fs()?
+            .symlink_metadata(path.as_ref())
+            .await
+            .map_err(|_| Error::from(ErrorKind::PermanentFileNotAvailable))
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(
+        &self,
+        _user: &FtpUser,
+        path: P,
+    ) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<FtpUser>>::Metadata: storage::Metadata,
+    {
+        let entries = self
+            .fs()?
+            .read_dir(path.as_ref())
+            .await
+            .map_err(storage_error)?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| Fileinfo {
+                path: PathBuf::from(entry.name),
+                metadata: entry.metadata,
+            })
+            .collect())
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(
+        &self,
+        user: &FtpUser,
+        path: P,
+        start_pos: u64,
+    ) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        let path = path.as_ref().to_path_buf();
+        let decision = self.services.policy.on_download(&path.to_string_lossy());
+        let path = apply_policy(user, path, decision)?;
+        let fs = self.fs()?;
+        let mut file = fs
+            .open(&path, OpenOptions::read())
+            .await
+            .map_err(storage_error)?;
+        if start_pos > 0 {
+            file.seek(SeekFrom::Start(start_pos)).await?;
+        }
+        let mut tracker = self.track(user, fs, &path, Direction::Download);
+        tracker.read_from(start_pos);
+        Ok(Box::new(Throttled::new(
+            Audited::new(file, tracker),
+            self.transfer.clone(),
+            Direction::Download,
+        )))
+    }
+
+    async fn put<
+        P: AsRef<Path> + Send + Debug,
+        R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
+    >(
+        &self,
+        user: &FtpUser,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        let path = path.as_ref().to_path_buf();
+        let requested = path.to_string_lossy().into_owned();
+        let decision = self.services.policy.on_upload(&requested, 0);
+        let path = apply_policy(user, path, decision)?;
+        let session = self.entered()?;
+        let fs = &session.fs;
+        // 超出配额或被策略拒绝时丢弃本次写入的内容：
+        // 已有文件截回原来的长度（覆盖上传时为空），新建的文件删除
+        let original = match fs.metadata(&path).await {
+            Ok(metadata) if start_pos > 0 => Some(metadata.len),
+            Ok(_) => Some(0),
+            Err(_) => None,
+        };
+        let options = OpenOptions {
+            write: true,
+            create: true,
+            truncate: start_pos == 0,
+            ..Default::default()
+        };
+        let mut file = fs.open(&path, options).await.map_err(storage_error)?;
+        if start_pos > 0 {
+            file.seek(SeekFrom::Start(start_pos)).await?;
+        }
+        // 在写入端统计，存储成功关闭文件后才记为完成并触发上传钩子
+        let mut file = Audited::new(file, self.track(user, fs, &path, Direction::Upload));
+
+        let input = PolicyChecked {
+            inner: input,
+            check: UploadCheck::new(
+                self.services.policy.clone(),
+                requested,
+                path.clone(),
+                start_pos,
+            ),
+            size: start_pos,
+        };
+        let mut input = Throttled::new(input, self.transfer.clone(), Direction::Upload);
+        let copied = match tokio::io::copy(&mut input, &mut file).await {
+            Ok(copied) => file.shutdown().await.map(|_| copied),
+            Err(e) => Err(e),
+        };
+        match copied {
+            Ok(copied) => Ok(copied),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::StorageFull | io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                if e.kind() == io::ErrorKind::StorageFull {
+                    warn!("{}: byte quota exceeded while uploading {:?}", user, path);
+                }
+                let restored = match original {
+                    Some(len) => file.get_mut().set_len(len).await,
+                    None => {
+                        drop(file);
+                        session.home.remove_file(&path).await
+                    }
+                };
+                if let Err(e) = restored {
+                    warn!("Failed to discard partial upload: {}", e);
+                }
+                Err(storage_error(e))
+            }
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, _user: &FtpUser, path: P) -> Result<()> {
+        self.fs()?
+            .remove_file(path.as_ref())
+            .await
+            .map_err(storage_error)
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, _user: &FtpUser, path: P) -> Result<()> {
+        self.fs()?
+            .create_dir(path.as_ref())
+            .await
+            .map_err(storage_error)
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(
+        &self,
+        _user: &FtpUser,
+        from: P,
+        to: P,
+    ) -> Result<()> {
+        self.fs()?
+            .rename(from.as_ref(), to.as_ref())
+            .await
+            .map_err(storage_error)
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, _user: &FtpUser, path: P) -> Result<()> {
+        self.fs()?
+            .remove_dir(path.as_ref())
+            .await
+            .map_err(storage_error)
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, _user: &FtpUser, path: P) -> Result<()> {
+        match self.fs()?.metadata(path.as_ref()).await {
+            Ok(metadata) if metadata.is_dir() => Ok(()),
+            Ok(_) => Err(Error::from(ErrorKind::PermanentDirectoryNotAvailable)),
+            Err(e) => Err(Error::new(ErrorKind::PermanentDirectoryNotAvailable, e)),
+        }
+    }
+}
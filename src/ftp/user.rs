@@ -0,0 +1,71 @@
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use unftp_core::auth::{Principal, UserDetail, UserDetailError, UserDetailProvider};
+
+use crate::accounts::{Access, AccountStore};
+use crate::permissions::PathRules;
+use crate::quota::Quota;
+
+/// FTP 会话中登录的用户
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FtpUser {
+    pub username: String,
+    /// 用户主目录的实际路径，`None` 表示整个共享
+    pub home: Option<PathBuf>,
+    pub quota: Quota,
+    pub access: Access,
+    pub permissions: PathRules,
+    pub anonymous: bool,
+}
+
+impl UserDetail for FtpUser {
+    fn home(&self) -> Option<&Path> {
+        self.home.as_deref()
+    }
+}
+
+impl Display for FtpUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.username)
+    }
+}
+
+#[derive(Debug)]
+pub struct FtpUserProvider {
+    accounts: Arc<AccountStore>,
+    root: PathBuf,
+}
+
+impl FtpUserProvider {
+    pub fn new(accounts: Arc<AccountStore>, root: PathBuf) -> Self {
+        Self { accounts, root }
+    }
+}
+
+#[async_trait]
+impl UserDetailProvider for FtpUserProvider {
+    type User = FtpUser;
+
+    async fn provide_user_detail(&self, principal: &Principal) -> Result<FtpUser, UserDetailError> {
+        let account = self.accounts.get(&principal.username).ok_or_else(|| {
+            UserDetailError::UserNotFound {
+                username: principal.username.clone(),
+            }
+        })?;
+        let dir = account
+            .home_dir(&self.root)
+            .map_err(|e| UserDetailError::with_source("failed to prepare home directory", e))?;
+        let home = (dir != self.root).then_some(dir);
+        Ok(FtpUser {
+            username: account.username.clone(),
+            home,
+            quota: account.quota(),
+            access: account.access,
+            permissions: account.permissions.clone(),
+            anonymous: account.anonymous,
+        })
+    }
+}
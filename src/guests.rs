@@ -0,0 +1,216 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, TimeDelta};
+use log::info;
+use rand::Rng;
+use rand::distr::Alphanumeric;
+
+use crate::accounts::{Access, Account};
+
+const USERNAME_PREFIX: &str = "guest-";
+const USERNAME_LEN: usize = 6;
+const PASSWORD_LEN: usize = 12;
+
+/// 界面上创建的临时访客账户，到期或下载次数用完后自动失效
+#[derive(Debug, Clone)]
+pub struct Guest {
+    /// 用户名和密码随机生成，`home` 为可访问的子目录
+    pub account: Account,
+    pub expires_at: Option<DateTime<Local>>,
+    pub max_downloads: Option<u32>,
+    /// 已完成的下载次数
+    pub downloads: u32,
+    /// 已在界面上删除，保留记录使已登录的会话也随之失效
+    revoked: bool,
+}
+
+impl Guest {
+    pub fn is_active(&self) -> bool {
+        !self.revoked
+            && self
+                .expires_at
+                .is_none_or(|expires_at| Local::now() < expires_at)
+            && self.max_downloads.is_none_or(|max| self.downloads < max)
+    }
+}
+
+/// 所有访客账户，仅保存在内存中，重启服务器不会清除
+#[derive(Debug, Default)]
+pub struct GuestStore {
+    guests: Mutex<Vec<Guest>>,
+}
+
+impl GuestStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 创建访客账户，`scope` 为相对于共享根目录的子目录，为空表示整个共享
+    pub fn create(
+        &self,
+        scope: Option<String>,
+        access: Access,
+        valid_for: Option<TimeDelta>,
+        max_downloads: Option<u32>,
+    ) -> Guest {
+        let mut guests = self.guests.lock().unwrap();
+        let username = loop {
+            let username = format!(
+                "{}{}",
+                USERNAME_PREFIX,
+                random_string(USERNAME_LEN).to_lowercase()
+            );
+            if !guests
+                .iter()
+                .any(|guest| guest.account.username == username)
+            {
+                break username;
+            }
+        };
+
+        let guest = Guest {
+            account: Account {
+                username,
+                password: random_string(PASSWORD_LEN),
+                home: scope,
+                access,
+                ..Default::default()
+            },
+            expires_at: valid_for.map(|valid_for| Local::now() + valid_for),
+            max_downloads,
+            downloads: 0,
+            revoked: false,
+        };
+        info!(
+            "Created guest account {} ({:?}, expires at {:?}, max downloads {:?})",
+            guest.account.username, access, guest.expires_at, max_downloads
+        );
+        guests.push(guest.clone());
+        guest
+    }
+
+    /// 删除访客账户，用户名不会再分配给新的访客
+    pub fn remove(&self, username: &str) {
+        let mut guests = self.guests.lock().unwrap();
+        if let Some(guest) = guests
+            .iter_mut()
+            .find(|guest| guest.account.username == username)
+        {
+            guest.revoked = true;
+            info!("Removed guest account {}", username);
+        }
+    }
+
+    /// 未删除的访客账户，包括已失效的
+    pub fn list(&self) -> Vec<Guest> {
+        self.guests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|guest| !guest.revoked)
+            .cloned()
+            .collect()
+    }
+
+    /// 是否为访客用户名，包括已失效和已删除的访客
+    pub fn contains(&self, username: &str) -> bool {
+        self.guests
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|guest| guest.account.username == username)
+    }
+
+    /// 校验仍有效的访客账户
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<Account> {
+        self.get(username)
+            .filter(|account| account.password == password)
+    }
+
+    pub fn get(&self, username: &str) -> Option<Account> {
+        self.guests
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|guest| guest.account.username == username && guest.is_active())
+            .map(|guest| guest.account.clone())
+    }
+
+    /// 已登录的会话在访客账户失效后也不能继续传输，非访客账户总是有效
+    pub fn is_active(&self, username: &str) -> bool {
+        self.guests
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|guest| guest.account.username == username)
+            .is_none_or(Guest::is_active)
+    }
+
+    /// 记录一次完成的下载
+    pub fn record_download(&self, username: &str) {
+        let mut guests = self.guests.lock().unwrap();
+        if let Some(guest) = guests
+            .iter_mut()
+            .find(|guest| guest.account.username == username)
+        {
+            guest.downloads += 1;
+            if !guest.is_active() {
+                info!("Guest account {} has been used up", username);
+            }
+        }
+    }
+}
+
+fn random_string(len: usize) -> String {
+    rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_guest_is_inactive() {
+        let guests = GuestStore::new();
+        let guest = guests.create(None, Access::ReadOnly, None, None);
+        let username = &guest.account.username;
+        assert!(guests.is_active(username));
+        assert!(
+            guests
+                .authenticate(username, &guest.account.password)
+                .is_some()
+        );
+
+        guests.remove(username);
+        assert!(!guests.is_active(username));
+        assert!(guests.contains(username));
+        assert!(guests.get(username).is_none());
+        assert!(
+            guests
+                .authenticate(username, &guest.account.password)
+                .is_none()
+        );
+        assert!(guests.list().is_empty());
+    }
+
+    #[test]
+    fn used_up_guest_is_inactive() {
+        let guests = GuestStore::new();
+        let guest = guests.create(None, Access::ReadOnly, None, Some(1));
+        let username = &guest.account.username;
+        guests.record_download(username);
+        assert!(!guests.is_active(username));
+        assert_eq!(guests.list().len(), 1);
+    }
+
+    #[test]
+    fn other_users_are_active() {
+        let guests = GuestStore::new();
+        assert!(guests.is_active("alice"));
+        assert!(!guests.contains("alice"));
+    }
+}
@@ -0,0 +1,181 @@
+use std::io;
+use std::process::Stdio;
+
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::runtime::Handle;
+
+use crate::audit::TransferSource;
+use crate::config::config_dir;
+
+/// 事件钩子配置文件名，位于配置目录下
+pub const HOOKS_FILE: &str = "hooks.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Upload,
+    Download,
+    Delete,
+    Login,
+    LoginFailed,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Upload => "upload",
+            EventKind::Download => "download",
+            EventKind::Delete => "delete",
+            EventKind::Login => "login",
+            EventKind::LoginFailed => "login_failed",
+        }
+    }
+}
+
+/// 触发钩子的事件
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub source: TransferSource,
+    pub path: Option<String>,
+    pub bytes: Option<u64>,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, source: TransferSource) -> Self {
+        Self {
+            kind,
+            source,
+            path: None,
+            bytes: None,
+        }
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::json!({
+            "event": self.kind.as_str(),
+            "protocol": self.source.protocol,
+            "username": self.source.username,
+            "anonymous": self.source.anonymous,
+            "remote_host": self.source.remote_host,
+            "path": self.path,
+            "bytes": self.bytes,
+        })
+        .to_string()
+    }
+
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("FTP_QUICK_EVENT", self.kind.as_str().to_string()),
+            ("FTP_QUICK_PROTOCOL", self.source.protocol.to_string()),
+            ("FTP_QUICK_USER", self.source.username.clone()),
+            ("FTP_QUICK_REMOTE_HOST", self.source.remote_host.clone()),
+        ];
+        if let Some(path) = &self.path {
+            env.push(("FTP_QUICK_PATH", path.clone()));
+        }
+        if let Some(bytes) = self.bytes {
+            env.push(("FTP_QUICK_BYTES", bytes.to_string()));
+        }
+        env
+    }
+}
+
+/// 一个事件钩子
+///
+/// 在 `config/hooks.json` 中配置，事件详情通过环境变量和标准输入的 JSON 传给命令：
+///
+/// ```json
+/// [
+///   { "event": "upload", "command": "flash.sh", "args": ["--verify"] }
+/// ]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Hook {
+    pub event: EventKind,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// 所有已配置的钩子，事件发生时在后台执行对应命令
+#[derive(Debug)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    runtime: Handle,
+}
+
+impl Hooks {
+    /// 读取钩子配置，需在 tokio 运行时中调用
+    pub fn load() -> Self {
+        let mut hooks = Vec::new();
+        let path = config_dir().join(HOOKS_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<Vec<Hook>>(&content) {
+                Ok(loaded) => {
+                    info!("Loaded {} hook(s) from {:?}", loaded.len(), path);
+                    hooks = loaded;
+                }
+                Err(e) => warn!("Failed to parse {:?}: {}", path, e),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to read {:?}: {}", path, e),
+        }
+
+        Self {
+            hooks,
+            runtime: Handle::current(),
+        }
+    }
+
+    pub fn fire(&self, event: Event) {
+        for hook in self.hooks.iter().filter(|hook| hook.event == event.kind) {
+            let hook = hook.clone();
+            let event = event.clone();
+            self.runtime.spawn(async move {
+                if let Err(e) = run(&hook, &event).await {
+                    warn!("Hook {:?} failed: {}", hook.command, e);
+                }
+            });
+        }
+    }
+}
+
+async fn run(hook: &Hook, event: &Event) -> io::Result<()> {
+    let mut child = Command::new(&hook.command)
+        .args(&hook.args)
+        .envs(event.env())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // 命令不读取标准输入时忽略写入错误
+        let _ = stdin.write_all(event.to_json().as_bytes()).await;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        warn!(
+            "Hook {:?} exited with {}: {}",
+            hook.command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
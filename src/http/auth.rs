@@ -0,0 +1,102 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use log::warn;
+
+use crate::accounts::Account;
+use crate::audit::TransferSource;
+use crate::hooks::{Event, EventKind};
+use crate::http::HttpState;
+
+/// 检查 IP 规则后进行 HTTP Basic 认证，使用与其他协议相同的账户；通过后把账户放入请求扩展
+///
+/// 匿名模式下不要求认证。每个请求都会带上凭据，因此只在认证失败时触发钩子。
+pub async fn basic_auth(
+    State(state): State<Arc<HttpState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !state.services.accepts(state.protocol, peer) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let remote_host = peer.ip().to_string();
+    let (username, account) = if state.accounts.is_anonymous() {
+        let username = "anonymous".to_string();
+        let account = Account::anonymous(&username);
+        (username, Some(account))
+    } else {
+        match credentials(request.headers()) {
+            Some((username, password)) => {
+                let lockouts = &state.services.lockouts;
+                if lockouts.is_locked(peer.ip(), &username) {
+                    warn!(
+                        "Rejected {} login of {} from {}: locked out",
+                        state.protocol, username, peer
+                    );
+                    return unauthorized();
+                }
+                let account = state.accounts.authenticate(&username, &password);
+                if account.is_some() {
+                    lockouts.record_success(&username);
+                } else {
+                    lockouts.record_failure(peer.ip(), &username);
+                    fire_login_failed(&state, &username, &remote_host);
+                }
+                (username, account)
+            }
+            None => return unauthorized(),
+        }
+    };
+
+    let account = account
+        .filter(|account| account.permits_ip(peer.ip()))
+        .filter(|_| {
+            state
+                .services
+                .policy
+                .authorize(&username, &remote_host, state.protocol)
+        });
+    match account {
+        Some(account) => {
+            request.extensions_mut().insert(account);
+            next.run(request).await
+        }
+        None => unauthorized(),
+    }
+}
+
+fn credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+fn fire_login_failed(state: &HttpState, username: &str, remote_host: &str) {
+    state.services.hooks.fire(Event::new(
+        EventKind::LoginFailed,
+        TransferSource {
+            protocol: state.protocol,
+            username: username.to_string(),
+            anonymous: false,
+            remote_host: remote_host.to_string(),
+        },
+    ));
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Basic realm=\"FTP-Quick\"")],
+    )
+        .into_response()
+}
@@ -0,0 +1,571 @@
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::Extension;
+use axum::body::Body;
+use axum::extract::multipart::Field;
+use axum::extract::{ConnectInfo, Multipart, State};
+use axum::http::{HeaderMap, Method, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use log::warn;
+use percent_encoding::percent_decode_str;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+use crate::accounts::Account;
+use crate::audit::{Audited, TransferSource, TransferTracker};
+use crate::http::{HttpState, index, sanitize, virtual_path};
+use crate::permissions::Permission;
+use crate::policy::UploadCheck;
+use crate::quota::{self, QUOTA_EXCEEDED};
+use crate::sftp::utils::path_resolver::PathResolver;
+use crate::throttle::{Direction, Throttled};
+
+/// 请求中的 `Range` 头
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    Full,
+    /// `[start, end)`
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// 解析单段字节范围，多段或格式不支持时按完整文件返回
+fn parse_range(value: &str, len: u64) -> RangeRequest {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((first, last)) = spec.split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let (first, last) = (first.trim(), last.trim());
+    let (start, end) = if first.is_empty() {
+        // 后缀范围：最后 n 个字节
+        match last.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(n) => (len.saturating_sub(n), len),
+            Err(_) => return RangeRequest::Full,
+        }
+    } else {
+        let Ok(start) = first.parse::<u64>() else {
+            return RangeRequest::Full;
+        };
+        let end = if last.is_empty() {
+            len
+        } else {
+            match last.parse::<u64>() {
+                Ok(last) if last >= start => (last + 1).min(len),
+                _ => return RangeRequest::Full,
+            }
+        };
+        (start, end)
+    };
+
+    if start >= len {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(start, end)
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, message.to_string()).into_response()
+}
+
+fn source(account: &Account, peer: SocketAddr) -> TransferSource {
+    TransferSource {
+        protocol: "http",
+        username: account.username.clone(),
+        anonymous: account.anonymous,
+        remote_host: peer.ip().to_string(),
+    }
+}
+
+/// 请求路径对应的相对路径
+fn request_path(uri: &Uri) -> Option<PathBuf> {
+    let decoded = percent_decode_str(uri.path()).decode_utf8().ok()?;
+    sanitize(&decoded)
+}
+
+fn home_dir(state: &HttpState, account: &Account) -> Result<PathBuf, Response> {
+    account.home_dir(&state.root).map_err(|e| {
+        warn!("Failed to prepare home directory: {}", e);
+        error(StatusCode::FORBIDDEN, "Forbidden")
+    })
+}
+
+/// 相对路径在主目录下对应的实际路径，与 SFTP 一样解析符号链接后检查是否仍在主目录内
+fn confine(home: &Path, relative: &Path) -> Result<PathBuf, Response> {
+    let root = home.canonicalize().map_err(|e| {
+        warn!("Failed to resolve home directory {:?}: {}", home, e);
+        error(StatusCode::FORBIDDEN, "Forbidden")
+    })?;
+    match PathResolver::new(root).resolve_path(&relative.to_string_lossy()) {
+        Ok(path) => Ok(path),
+        Err(russh_sftp::protocol::StatusCode::PermissionDenied) => {
+            Err(error(StatusCode::FORBIDDEN, "Forbidden"))
+        }
+        Err(_) => Err(error(StatusCode::NOT_FOUND, "Not found")),
+    }
+}
+
+/// 检查共享模式和账户在该路径上的权限，`relative` 为相对于主目录的路径
+fn permits(state: &HttpState, account: &Account, relative: &Path, permission: Permission) -> bool {
+    state.services.permits(
+        &account.username,
+        account.permissions_at(relative),
+        permission,
+    )
+}
+
+/// 目录返回索引页，文件支持断点续传的范围请求
+pub async fn serve(
+    State(state): State<Arc<HttpState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(account): Extension<Account>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    let Some(relative) = request_path(&uri) else {
+        return error(StatusCode::BAD_REQUEST, "Invalid path");
+    };
+    let home = match home_dir(&state, &account) {
+        Ok(home) => home,
+        Err(response) => return response,
+    };
+
+    let path = match confine(&home, &relative) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if path.is_dir() {
+        if !uri.path().ends_with('/') {
+            return Redirect::permanent(&format!("{}/", uri.path())).into_response();
+        }
+        let list = permits(&state, &account, &relative, Permission::List);
+        let upload = permits(&state, &account, &relative, Permission::Upload);
+        return index::render(&path, &virtual_path(&relative), list, upload).await;
+    }
+
+    let requested = virtual_path(&relative);
+    let decision = state.services.policy.on_download(&requested);
+    let Some(relative) = decision.apply(requested).and_then(|path| sanitize(&path)) else {
+        return error(StatusCode::FORBIDDEN, "Denied by policy");
+    };
+    if !permits(&state, &account, &relative, Permission::Download) {
+        return error(StatusCode::FORBIDDEN, "Forbidden");
+    }
+    let path = match confine(&home, &relative) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    download(&state, &account, peer, &path, &method, &headers).await
+}
+
+async fn download(
+    state: &HttpState,
+    account: &Account,
+    peer: SocketAddr,
+    path: &Path,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Response {
+    let mut file = match File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return error(StatusCode::NOT_FOUND, "Not found");
+        }
+        Err(e) => {
+            warn!("Failed to open {:?}: {}", path, e);
+            return error(StatusCode::FORBIDDEN, "Forbidden");
+        }
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            warn!("Failed to read metadata of {:?}: {}", path, e);
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error");
+        }
+    };
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(RangeRequest::Full, |value| parse_range(value, len));
+    let (status, start, end) = match range {
+        RangeRequest::Full => (StatusCode::OK, 0, len),
+        RangeRequest::Partial(start, end) => (StatusCode::PARTIAL_CONTENT, start, end),
+        RangeRequest::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+            )
+                .into_response();
+        }
+    };
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(
+            header::CONTENT_TYPE,
+            mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .essence_str(),
+        )
+        .header(header::CONTENT_LENGTH, end - start)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end - 1, len),
+        );
+    }
+    if *method == Method::HEAD {
+        return response.body(Body::empty()).unwrap();
+    }
+
+    if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+        warn!("Failed to seek in {:?}: {}", path, e);
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error");
+    }
+    let mut tracker = TransferTracker::new(
+        &state.services,
+        source(account, peer),
+        path.to_string_lossy().into_owned(),
+        Direction::Download,
+    );
+    if start > 0 || end < len {
+        tracker.set_partial();
+    }
+    let reader = Throttled::new(
+        Audited::new(file.take(end - start), tracker),
+        state.services.throttle.session(&account.username),
+        Direction::Download,
+    );
+    response
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .unwrap()
+}
+
+/// 处理索引页的上传表单，文件保存到当前目录
+pub async fn upload(
+    State(state): State<Arc<HttpState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(account): Extension<Account>,
+    uri: Uri,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    // 浏览器会自动附带 Basic 认证凭据，须拒绝其他网站提交的表单
+    if !same_origin(&headers) {
+        warn!(
+            "{}: rejected cross-site upload from {}",
+            account.username, peer
+        );
+        return error(StatusCode::FORBIDDEN, "Cross-site upload rejected");
+    }
+    let Some(relative) = request_path(&uri) else {
+        return error(StatusCode::BAD_REQUEST, "Invalid path");
+    };
+    let home = match home_dir(&state, &account) {
+        Ok(home) => home,
+        Err(response) => return response,
+    };
+    let directory = virtual_path(&relative);
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return error(StatusCode::BAD_REQUEST, &e.body_text()),
+        };
+        let Some(name) = field
+            .file_name()
+            .and_then(|name| Path::new(name).file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+        else {
+            continue;
+        };
+
+        let requested = format!("{}/{}", directory.trim_end_matches('/'), name);
+        if let Err(response) = save(&state, &account, peer, &home, requested, field).await {
+            return response;
+        }
+    }
+
+    Redirect::to(uri.path()).into_response()
+}
+
+async fn save(
+    state: &HttpState,
+    account: &Account,
+    peer: SocketAddr,
+    home: &Path,
+    requested: String,
+    mut field: Field<'_>,
+) -> Result<(), Response> {
+    let decision = state.services.policy.on_upload(&requested, 0);
+    let Some(target) = decision.apply(requested.clone()) else {
+        return Err(error(StatusCode::FORBIDDEN, "Denied by policy"));
+    };
+    let Some(relative) = sanitize(&target) else {
+        return Err(error(StatusCode::FORBIDDEN, "Denied by policy"));
+    };
+    let path = confine(home, &relative)?;
+    let existed = path.exists();
+    let permission = if existed {
+        Permission::Overwrite
+    } else {
+        Permission::Upload
+    };
+    if !permits(state, account, &relative, permission) {
+        return Err(error(StatusCode::FORBIDDEN, "Forbidden"));
+    }
+
+    // 根据配额计算本次最多可写入的字节数
+    let quota = account.quota();
+    let allowance = if quota.is_unlimited() {
+        None
+    } else {
+        let mut usage = quota::usage(home.to_path_buf()).await.map_err(|e| {
+            warn!("Failed to compute quota usage: {}", e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error")
+        })?;
+        match fs::metadata(&path).await {
+            Ok(metadata) => usage.bytes = usage.bytes.saturating_sub(metadata.len()),
+            Err(_) => {
+                if !quota.allows_new_file(&usage) {
+                    return Err(error(StatusCode::INSUFFICIENT_STORAGE, QUOTA_EXCEEDED));
+                }
+            }
+        }
+        quota.remaining_bytes(&usage)
+    };
+
+    let mut file = File::create(&path).await.map_err(|e| {
+        warn!("Failed to create {:?}: {}", path, e);
+        error(StatusCode::FORBIDDEN, "Forbidden")
+    })?;
+    let mut tracker = TransferTracker::new(
+        &state.services,
+        source(account, peer),
+        path.to_string_lossy().into_owned(),
+        Direction::Upload,
+    );
+    let transfer = state.services.throttle.session(&account.username);
+    let mut check = UploadCheck::new(
+        state.services.policy.clone(),
+        requested,
+        PathBuf::from(target),
+        0,
+    );
+
+    let mut written = 0u64;
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => return Err(error(StatusCode::BAD_REQUEST, &e.body_text())),
+        };
+        written += chunk.len() as u64;
+        if allowance.is_some_and(|allowance| written > allowance) {
+            warn!(
+                "{}: byte quota exceeded while uploading {:?}",
+                account.username, path
+            );
+            discard(file, existed, &path).await;
+            return Err(error(StatusCode::INSUFFICIENT_STORAGE, QUOTA_EXCEEDED));
+        }
+        if !check.wrote(written) {
+            discard(file, existed, &path).await;
+            return Err(error(StatusCode::FORBIDDEN, "Denied by policy"));
+        }
+
+        transfer
+            .consume(Direction::Upload, chunk.len() as u64)
+            .await;
+        file.write_all(&chunk).await.map_err(|e| {
+            warn!("Failed to write {:?}: {}", path, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error")
+        })?;
+        tracker.add(chunk.len() as u64);
+    }
+    if !check.finish() {
+        discard(file, existed, &path).await;
+        return Err(error(StatusCode::FORBIDDEN, "Denied by policy"));
+    }
+
+    file.flush().await.map_err(|e| {
+        warn!("Failed to flush {:?}: {}", path, e);
+        error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error")
+    })?;
+    tracker.set_complete();
+    Ok(())
+}
+
+/// 表单是否由本站页面提交：`Origin`（没有时为 `Referer`）的主机和端口须与 `Host` 一致，
+/// 两者都没有时拒绝
+fn same_origin(headers: &HeaderMap) -> bool {
+    let Some(host) = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+    else {
+        return false;
+    };
+    let Some(source) = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .and_then(|source| source.to_str().ok())
+    else {
+        return false;
+    };
+    let Some((_, rest)) = source.split_once("://") else {
+        return false;
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    authority.eq_ignore_ascii_case(host)
+}
+
+/// 丢弃被拒绝的上传，覆盖的文件打开时已被截断，只删除本次新建的文件
+async fn discard(file: File, existed: bool, path: &Path) {
+    let discarded = if existed {
+        file.set_len(0).await
+    } else {
+        drop(file);
+        fs::remove_file(path).await
+    };
+    if let Err(e) = discarded {
+        warn!("Failed to discard partial upload: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn same_origin_compares_origin_or_referer_with_host() {
+        let host = (header::HOST, "192.168.1.2:8080");
+        assert!(same_origin(&headers(&[
+            host.clone(),
+            (header::ORIGIN, "http://192.168.1.2:8080"),
+        ])));
+        assert!(same_origin(&headers(&[
+            host.clone(),
+            (header::REFERER, "http://192.168.1.2:8080/dir/?x=1"),
+        ])));
+        assert!(!same_origin(&headers(&[
+            host.clone(),
+            (header::ORIGIN, "http://evil.example"),
+            (header::REFERER, "http://192.168.1.2:8080/"),
+        ])));
+        assert!(!same_origin(&headers(&[
+            host.clone(),
+            (header::ORIGIN, "http://192.168.1.2:8081"),
+        ])));
+        assert!(!same_origin(&headers(&[
+            host.clone(),
+            (header::ORIGIN, "null")
+        ])));
+        assert!(!same_origin(&headers(&[host])));
+        assert!(!same_origin(&headers(&[(
+            header::ORIGIN,
+            "http://192.168.1.2:8080"
+        )])));
+    }
+
+    #[test]
+    fn parse_range_single() {
+        assert_eq!(
+            parse_range("bytes=0-99", 1000),
+            RangeRequest::Partial(0, 100)
+        );
+        assert_eq!(
+            parse_range("bytes=500-", 1000),
+            RangeRequest::Partial(500, 1000)
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            RangeRequest::Partial(900, 1000)
+        );
+        assert_eq!(
+            parse_range(" bytes= 10 - 19 ", 1000),
+            RangeRequest::Partial(10, 20)
+        );
+    }
+
+    #[test]
+    fn parse_range_clamps_to_length() {
+        assert_eq!(
+            parse_range("bytes=900-1999", 1000),
+            RangeRequest::Partial(900, 1000)
+        );
+        assert_eq!(
+            parse_range("bytes=-5000", 1000),
+            RangeRequest::Partial(0, 1000)
+        );
+    }
+
+    #[test]
+    fn parse_range_unsatisfiable() {
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn parse_range_falls_back_to_full() {
+        assert_eq!(parse_range("items=0-1", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=5-1", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=10", 1000), RangeRequest::Full);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn confine_rejects_symlinks_out_of_home() {
+        let base = std::env::temp_dir().join(format!("http-confine-{}", std::process::id()));
+        let home = base.join("home");
+        let outside = base.join("outside");
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret"), "secret").unwrap();
+        std::fs::write(home.join("file"), "file").unwrap();
+        std::os::unix::fs::symlink(&outside, home.join("link")).unwrap();
+
+        let canonical = home.canonicalize().unwrap();
+        assert_eq!(
+            confine(&home, Path::new("file")).unwrap(),
+            canonical.join("file")
+        );
+        assert_eq!(
+            confine(&home, Path::new("new")).unwrap(),
+            canonical.join("new")
+        );
+        for relative in ["link", "link/secret", "link/new"] {
+            let response = confine(&home, Path::new(relative)).unwrap_err();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", relative);
+        }
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
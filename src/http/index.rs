@@ -0,0 +1,126 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use chrono::{DateTime, Local};
+use log::warn;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use tokio::fs;
+
+/// 链接中需要编码的字符，保留常见的文件名字符
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'~');
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<DateTime<Local>>,
+}
+
+/// 生成目录索引页，`list` 为假时只显示上传表单，`upload` 为假时不显示上传表单
+pub async fn render(dir: &Path, virtual_dir: &str, list: bool, upload: bool) -> Response {
+    let mut entries = Vec::new();
+    if list {
+        let mut read_dir = match fs::read_dir(dir).await {
+            Ok(read_dir) => read_dir,
+            Err(e) => {
+                warn!("Failed to read directory {:?}: {}", dir, e);
+                return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+            }
+        };
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            entries.push(Entry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok().map(DateTime::from),
+            });
+        }
+    }
+    // 目录在前，按名称排序
+    entries.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+
+    let title = escape(virtual_dir);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>FTP-Quick - {title}</title>\
+         <style>body{{font-family:sans-serif;margin:1em}}table{{border-collapse:collapse;width:100%}}\
+         td{{padding:4px 8px;border-bottom:1px solid #ddd}}td.size,td.time{{white-space:nowrap;color:#666}}</style>\
+         </head><body>\n<h2>{title}</h2>\n"
+    );
+    if upload {
+        html.push_str(
+            "<form method=\"post\" enctype=\"multipart/form-data\">\
+             <input type=\"file\" name=\"file\" multiple> <button type=\"submit\">上传</button></form>\n",
+        );
+    }
+    html.push_str("<table>\n");
+    if virtual_dir != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in &entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            String::new()
+        } else {
+            format_size(entry.size)
+        };
+        let modified = entry
+            .modified
+            .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{href}{suffix}\">{name}{suffix}</a></td>\
+             <td class=\"size\">{size}</td><td class=\"time\">{modified}</td></tr>",
+            href = utf8_percent_encode(&entry.name, PATH_SEGMENT),
+            name = escape(&entry.name),
+        );
+    }
+    html.push_str("</table>\n</body></html>\n");
+
+    Html(html).into_response()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
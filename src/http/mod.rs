@@ -0,0 +1,90 @@
+mod auth;
+mod files;
+mod index;
+
+pub(crate) use auth::basic_auth;
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use axum::Router;
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
+use axum::routing::get;
+
+use crate::accounts::AccountStore;
+use crate::server::Services;
+
+/// HTTP 服务器各请求共享的状态
+#[derive(Debug)]
+pub struct HttpState {
+    /// 认证时传给策略脚本和钩子的协议名
+    pub protocol: &'static str,
+    pub root: PathBuf,
+    pub accounts: Arc<AccountStore>,
+    pub services: Services,
+}
+
+pub fn router(state: Arc<HttpState>) -> Router {
+    Router::new()
+        .route("/", get(files::serve).post(files::upload))
+        .route("/{*path}", get(files::serve).post(files::upload))
+        .layer(middleware::from_fn_with_state(state.clone(), basic_auth))
+        // 上传大小由配额限制，不使用 axum 默认的 2MB 上限
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state)
+}
+
+/// 将客户端路径转换为相对路径，拒绝 `..`、盘符等越界组件
+fn sanitize(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for part in path
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+    {
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => relative.push(part),
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
+
+/// 相对路径对应的客户端路径，以 `/` 开头
+fn virtual_path(relative: &Path) -> String {
+    let parts: Vec<_> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    format!("/{}", parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_keeps_normal_components() {
+        assert_eq!(sanitize("/"), Some(PathBuf::new()));
+        assert_eq!(sanitize(""), Some(PathBuf::new()));
+        assert_eq!(sanitize("/a/./b//c/"), Some(PathBuf::from("a/b/c")));
+        assert_eq!(sanitize("a\\b"), Some(PathBuf::from("a/b")));
+        assert_eq!(sanitize("/..."), Some(PathBuf::from("...")));
+    }
+
+    #[test]
+    fn sanitize_rejects_escapes() {
+        assert_eq!(sanitize(".."), None);
+        assert_eq!(sanitize("/a/../b"), None);
+        assert_eq!(sanitize("a\\..\\b"), None);
+        #[cfg(windows)]
+        assert_eq!(sanitize("C:/Windows"), None);
+    }
+
+    #[test]
+    fn virtual_path_is_absolute() {
+        assert_eq!(virtual_path(Path::new("")), "/");
+        assert_eq!(virtual_path(Path::new("a/b")), "/a/b");
+    }
+}
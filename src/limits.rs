@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::warn;
+
+/// 连接数和会话时长限制，`None` 表示不限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionLimits {
+    pub max_connections: Option<u32>,
+    pub max_per_ip: Option<u32>,
+    pub max_per_user: Option<u32>,
+    /// 没有任何命令或数据时断开会话
+    pub idle_timeout: Duration,
+    /// 会话最长持续时间，到期后强制断开
+    pub max_duration: Option<Duration>,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_per_ip: None,
+            max_per_user: None,
+            idle_timeout: Duration::from_secs(10 * 60),
+            max_duration: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: u32,
+    per_ip: HashMap<IpAddr, u32>,
+    per_user: HashMap<String, u32>,
+}
+
+/// FTP 和 SFTP 共享的连接计数，连接和登录时检查上限
+#[derive(Debug, Default)]
+pub struct Connections {
+    limits: Mutex<SessionLimits>,
+    counts: Mutex<Counts>,
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn limits(&self) -> SessionLimits {
+        *self.limits.lock().unwrap()
+    }
+
+    /// 修改后对新连接生效，SFTP 的空闲超时在启动服务器时确定
+    pub fn set_limits(&self, limits: SessionLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    /// 接受连接时调用，超过全局或单 IP 上限时拒绝并记录日志
+    pub fn open(self: &Arc<Self>, protocol: &str, peer: SocketAddr) -> Option<ConnectionGuard> {
+        let limits = self.limits();
+        let ip = peer.ip().to_canonical();
+        let mut counts = self.counts.lock().unwrap();
+        if limits
+            .max_connections
+            .is_some_and(|max| counts.total >= max)
+        {
+            warn!(
+                "Refused {} connection from {}: too many connections",
+                protocol, peer
+            );
+            return None;
+        }
+        let per_ip = counts.per_ip.get(&ip).copied().unwrap_or(0);
+        if limits.max_per_ip.is_some_and(|max| per_ip >= max) {
+            warn!(
+                "Refused {} connection from {}: too many connections from this address",
+                protocol, peer
+            );
+            return None;
+        }
+
+        counts.total += 1;
+        *counts.per_ip.entry(ip).or_default() += 1;
+        Some(ConnectionGuard {
+            connections: self.clone(),
+            ip,
+        })
+    }
+
+    /// 登录成功时调用，超过单用户上限时拒绝登录
+    pub fn login(self: &Arc<Self>, username: &str) -> Option<UserGuard> {
+        let limits = self.limits();
+        let mut counts = self.counts.lock().unwrap();
+        let per_user = counts.per_user.get(username).copied().unwrap_or(0);
+        if limits.max_per_user.is_some_and(|max| per_user >= max) {
+            warn!("Refused login of {}: too many sessions", username);
+            return None;
+        }
+
+        *counts.per_user.entry(username.to_string()).or_default() += 1;
+        Some(UserGuard {
+            connections: self.clone(),
+            username: username.to_string(),
+        })
+    }
+}
+
+/// 连接期间持有，断开时释放计数
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    connections: Arc<Connections>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.connections.counts.lock().unwrap();
+        counts.total = counts.total.saturating_sub(1);
+        release(&mut counts.per_ip, &self.ip);
+    }
+}
+
+/// 登录后持有，会话结束时释放该用户的计数
+#[derive(Debug)]
+pub struct UserGuard {
+    connections: Arc<Connections>,
+    username: String,
+}
+
+impl Drop for UserGuard {
+    fn drop(&mut self) {
+        let mut counts = self.connections.counts.lock().unwrap();
+        release(&mut counts.per_user, &self.username);
+    }
+}
+
+fn release<K: Eq + std::hash::Hash>(counts: &mut HashMap<K, u32>, key: &K) {
+    if let Some(count) = counts.get_mut(key) {
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            counts.remove(key);
+        }
+    }
+}
+
+/// 运行会话，超过最长持续时间时返回 `None`，会话随之被丢弃
+pub async fn with_max_duration<F: Future>(
+    max_duration: Option<Duration>,
+    session: F,
+) -> Option<F::Output> {
+    match max_duration {
+        Some(max_duration) => tokio::time::timeout(max_duration, session).await.ok(),
+        None => Some(session.await),
+    }
+}
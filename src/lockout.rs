@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+/// 单个连接允许的认证次数，超过后断开或拒绝后续尝试
+pub const MAX_AUTH_ATTEMPTS: u32 = 6;
+
+/// 超过该时间没有新的失败时重新计数
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// 连续锁定时时长翻倍，但不超过一天
+const MAX_LOCKOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 锁定阈值和时长，可在界面上调整
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutSettings {
+    /// 连续失败多少次后锁定，0 表示不锁定
+    pub max_failures: u32,
+    /// 首次锁定的时长，之后每次翻倍
+    pub duration: Duration,
+}
+
+impl Default for LockoutSettings {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            duration: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// 按 IP 或用户名统计失败次数
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LockoutKey {
+    Ip(IpAddr),
+    User(String),
+}
+
+impl Display for LockoutKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LockoutKey::Ip(ip) => write!(f, "ip:{}", ip),
+            LockoutKey::User(username) => write!(f, "user:{}", username),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    failures: u32,
+    /// 已被锁定的次数，用于计算退避时长
+    strikes: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// 当前被锁定的 IP 或用户
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    pub key: LockoutKey,
+    pub remaining: Duration,
+    pub strikes: u32,
+}
+
+/// 跨协议的登录失败统计，连续失败过多时临时锁定 IP 和用户名
+#[derive(Debug, Default)]
+pub struct Lockouts {
+    settings: Mutex<LockoutSettings>,
+    entries: Mutex<HashMap<LockoutKey, Entry>>,
+}
+
+impl Lockouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_settings(&self, settings: LockoutSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    /// 该 IP 或用户名是否处于锁定期，锁定期间不校验密码
+    pub fn is_locked(&self, ip: IpAddr, username: &str) -> bool {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        keys(ip, username).iter().any(|key| {
+            entries
+                .get(key)
+                .and_then(|entry| entry.locked_until)
+                .is_some_and(|until| until > now)
+        })
+    }
+
+    pub fn record_failure(&self, ip: IpAddr, username: &str) {
+        let settings = *self.settings.lock().unwrap();
+        if settings.max_failures == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys(ip, username) {
+            let entry = entries.entry(key.clone()).or_insert(Entry {
+                failures: 0,
+                strikes: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+            if now.duration_since(entry.last_failure) > FAILURE_WINDOW {
+                entry.failures = 0;
+            }
+            entry.failures += 1;
+            entry.last_failure = now;
+
+            if entry.failures >= settings.max_failures {
+                let duration = settings
+                    .duration
+                    .saturating_mul(2u32.saturating_pow(entry.strikes))
+                    .min(MAX_LOCKOUT);
+                entry.strikes += 1;
+                entry.failures = 0;
+                entry.locked_until = Some(now + duration);
+                warn!(
+                    "Locked out {} for {}s after repeated login failures",
+                    key,
+                    duration.as_secs()
+                );
+            }
+        }
+    }
+
+    /// 登录成功后清除该用户名的失败记录
+    ///
+    /// IP 的记录不清除：同一地址可能一边用自己的账户登录，一边试探其他账户的密码，
+    /// HTTP 更是每个请求都会认证一次。
+    pub fn record_success(&self, username: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&LockoutKey::User(username.to_string()));
+    }
+
+    /// 当前处于锁定期的条目，同时清理已过期的记录
+    pub fn list(&self) -> Vec<Lockout> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| {
+            entry.locked_until.is_some_and(|until| until > now)
+                || now.duration_since(entry.last_failure) < MAX_LOCKOUT
+        });
+
+        let mut lockouts: Vec<_> = entries
+            .iter()
+            .filter_map(|(key, entry)| {
+                let until = entry.locked_until.filter(|until| *until > now)?;
+                Some(Lockout {
+                    key: key.clone(),
+                    remaining: until - now,
+                    strikes: entry.strikes,
+                })
+            })
+            .collect();
+        lockouts.sort_by_key(|lockout| lockout.key.to_string());
+        lockouts
+    }
+
+    /// 解除锁定，`id` 为 [`LockoutKey`] 的字符串形式
+    pub fn clear(&self, id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|key, _| key.to_string() != id);
+        info!("Cleared lockout {}", id);
+    }
+
+    pub fn clear_all(&self) {
+        self.entries.lock().unwrap().clear();
+        info!("Cleared all lockouts");
+    }
+}
+
+fn keys(ip: IpAddr, username: &str) -> Vec<LockoutKey> {
+    let mut keys = vec![LockoutKey::Ip(ip.to_canonical())];
+    if !username.is_empty() {
+        keys.push(LockoutKey::User(username.to_string()));
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 2));
+
+    fn lockouts(max_failures: u32) -> Lockouts {
+        let lockouts = Lockouts::new();
+        lockouts.set_settings(LockoutSettings {
+            max_failures,
+            duration: Duration::from_secs(60),
+        });
+        lockouts
+    }
+
+    /// 把该条目的时间往前拨，模拟经过了 `elapsed`
+    fn elapse(lockouts: &Lockouts, key: &LockoutKey, elapsed: Duration) {
+        let mut entries = lockouts.entries.lock().unwrap();
+        let entry = entries.get_mut(key).unwrap();
+        entry.last_failure = entry.last_failure.checked_sub(elapsed).unwrap();
+        entry.locked_until = entry
+            .locked_until
+            .map(|until| until.checked_sub(elapsed).unwrap());
+    }
+
+    #[test]
+    fn locks_after_threshold() {
+        let lockouts = lockouts(3);
+        lockouts.record_failure(IP, "alice");
+        lockouts.record_failure(IP, "alice");
+        assert!(!lockouts.is_locked(IP, "alice"));
+        lockouts.record_failure(IP, "alice");
+        assert!(lockouts.is_locked(IP, "alice"));
+        // IP 和用户名分别锁定
+        assert!(lockouts.is_locked(IP, "bob"));
+        assert!(lockouts.is_locked("10.0.0.1".parse().unwrap(), "alice"));
+        assert!(!lockouts.is_locked("10.0.0.1".parse().unwrap(), "bob"));
+        assert_eq!(lockouts.list().len(), 2);
+    }
+
+    #[test]
+    fn mapped_ipv4_shares_the_ipv4_count() {
+        let lockouts = lockouts(2);
+        lockouts.record_failure(IP, "");
+        lockouts.record_failure("::ffff:192.168.1.2".parse().unwrap(), "");
+        assert!(lockouts.is_locked(IP, ""));
+    }
+
+    #[test]
+    fn zero_threshold_never_locks() {
+        let lockouts = lockouts(0);
+        for _ in 0..10 {
+            lockouts.record_failure(IP, "alice");
+        }
+        assert!(!lockouts.is_locked(IP, "alice"));
+        assert!(lockouts.list().is_empty());
+    }
+
+    #[test]
+    fn failures_reset_after_window() {
+        let lockouts = lockouts(2);
+        lockouts.record_failure(IP, "alice");
+        elapse(
+            &lockouts,
+            &LockoutKey::Ip(IP),
+            FAILURE_WINDOW + Duration::from_secs(1),
+        );
+        elapse(
+            &lockouts,
+            &LockoutKey::User("alice".to_string()),
+            FAILURE_WINDOW + Duration::from_secs(1),
+        );
+        lockouts.record_failure(IP, "alice");
+        assert!(!lockouts.is_locked(IP, "alice"));
+        lockouts.record_failure(IP, "alice");
+        assert!(lockouts.is_locked(IP, "alice"));
+    }
+
+    #[test]
+    fn lockout_expires_and_doubles() {
+        let lockouts = lockouts(1);
+        let key = LockoutKey::Ip(IP);
+        lockouts.record_failure(IP, "");
+        assert!(lockouts.is_locked(IP, ""));
+        let remaining = lockouts.list()[0].remaining;
+        assert!(remaining <= Duration::from_secs(60) && remaining > Duration::from_secs(59));
+
+        elapse(&lockouts, &key, Duration::from_secs(61));
+        assert!(!lockouts.is_locked(IP, ""));
+
+        lockouts.record_failure(IP, "");
+        let lockout = &lockouts.list()[0];
+        assert_eq!(lockout.strikes, 2);
+        assert!(lockout.remaining > Duration::from_secs(119));
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        let lockouts = Lockouts::new();
+        lockouts.set_settings(LockoutSettings {
+            max_failures: 1,
+            duration: Duration::from_secs(u64::MAX),
+        });
+        lockouts.record_failure(IP, "");
+        assert!(lockouts.list()[0].remaining <= MAX_LOCKOUT);
+    }
+
+    #[test]
+    fn success_keeps_ip_failures() {
+        let lockouts = lockouts(3);
+        lockouts.record_failure(IP, "alice");
+        lockouts.record_failure(IP, "alice");
+        lockouts.record_success("alice");
+        // 用户名的计数已清除，IP 的计数保留
+        lockouts.record_failure(IP, "alice");
+        assert!(lockouts.is_locked(IP, "bob"));
+        assert!(!lockouts.is_locked("10.0.0.1".parse().unwrap(), "alice"));
+    }
+
+    #[test]
+    fn clear_unlocks() {
+        let lockouts = lockouts(1);
+        lockouts.record_failure(IP, "alice");
+        lockouts.clear("user:alice");
+        assert!(!lockouts.is_locked("10.0.0.1".parse().unwrap(), "alice"));
+        assert!(lockouts.is_locked(IP, "bob"));
+        lockouts.clear_all();
+        assert!(!lockouts.is_locked(IP, "alice"));
+    }
+}
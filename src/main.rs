@@ -1,15 +1,49 @@
 #![windows_subsystem = "windows"]
 
+mod accounts;
+mod acl;
+mod audit;
+mod config;
+mod ftp;
+mod guests;
+mod hooks;
+mod http;
+mod limits;
+mod lockout;
+mod mdns;
+mod net;
+mod permissions;
+mod policy;
+mod qr;
+mod quota;
 mod server;
 mod sftp;
 mod ssh;
+mod tftp;
+mod throttle;
+mod vfs;
+mod webdav;
 
-use slint::SharedString;
-use std::path::Path;
+use chrono::TimeDelta;
+use slint::{ModelRc, SharedString, VecModel};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::Arc;
 use tokio::sync::{Mutex, mpsc};
 use tokio::task::JoinHandle;
 
+use crate::accounts::Access;
+use crate::acl::IpRules;
+use crate::audit::TransferLog;
+use crate::guests::GuestStore;
+use crate::hooks::Hooks;
+use crate::limits::{Connections, SessionLimits};
+use crate::lockout::{LockoutKey, LockoutSettings, Lockouts};
+use crate::policy::Policy;
+use crate::throttle::{BandwidthLimits, Throttle};
+use crate::vfs::{ArchiveFs, CryptFs, MemoryFs, MountPoint, S3Fs, SftpFs, Vfs};
+
 slint::include_modules!();
 
 fn validate_path(path_str: &str) -> Result<String, String> {
@@ -25,18 +59,269 @@ fn validate_path(path_str: &str) -> Result<String, String> {
     }
 }
 
+/// 解析以 KB/s 为单位的限速值，留空视为不限速
+fn parse_rate_limit(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    value
+        .parse::<u64>()
+        .ok()
+        .and_then(|kb| kb.checked_mul(1024))
+        .ok_or_else(|| format!("无效限速值: {}", value))
+}
+
+/// 内存存储的默认上限
+const DEFAULT_MEMORY_LIMIT_MB: u64 = 256;
+
+/// 解析以 MB 为单位的内存存储上限，留空使用默认值
+fn parse_memory_limit(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(DEFAULT_MEMORY_LIMIT_MB * 1024 * 1024);
+    }
+    value
+        .parse::<u64>()
+        .ok()
+        .filter(|mb| *mb > 0)
+        .and_then(|mb| mb.checked_mul(1024 * 1024))
+        .ok_or_else(|| format!("无效内存上限: {}", value))
+}
+
+fn parse_bandwidth_limits(
+    upload: &str,
+    download: &str,
+    session: &str,
+    user: &str,
+) -> Result<BandwidthLimits, String> {
+    Ok(BandwidthLimits {
+        upload: parse_rate_limit(upload)?,
+        download: parse_rate_limit(download)?,
+        session: parse_rate_limit(session)?,
+        user: parse_rate_limit(user)?,
+    })
+}
+
+/// 解析访客账户的有效期（小时）和下载次数，留空表示不限，但不能都不限
+fn parse_guest_limits(
+    hours: &str,
+    downloads: &str,
+) -> Result<(Option<TimeDelta>, Option<u32>), String> {
+    let (hours, downloads) = (hours.trim(), downloads.trim());
+    let valid_for = if hours.is_empty() {
+        None
+    } else {
+        let hours = hours
+            .parse::<u32>()
+            .ok()
+            .filter(|hours| *hours > 0)
+            .ok_or_else(|| format!("无效有效期: {}", hours))?;
+        Some(TimeDelta::hours(hours.into()))
+    };
+    let max_downloads = if downloads.is_empty() {
+        None
+    } else {
+        Some(
+            downloads
+                .parse::<u32>()
+                .ok()
+                .filter(|downloads| *downloads > 0)
+                .ok_or_else(|| format!("无效下载次数: {}", downloads))?,
+        )
+    };
+    if valid_for.is_none() && max_downloads.is_none() {
+        return Err("请设置有效期或下载次数".to_string());
+    }
+    Ok((valid_for, max_downloads))
+}
+
+/// 解析登录失败锁定设置，留空使用默认值，失败次数为 0 表示不锁定
+fn parse_lockout_settings(max_failures: &str, minutes: &str) -> Result<LockoutSettings, String> {
+    let defaults = LockoutSettings::default();
+    let (max_failures, minutes) = (max_failures.trim(), minutes.trim());
+    let max_failures = if max_failures.is_empty() {
+        defaults.max_failures
+    } else {
+        max_failures
+            .parse::<u32>()
+            .map_err(|_| format!("无效失败次数: {}", max_failures))?
+    };
+    let duration = if minutes.is_empty() {
+        defaults.duration
+    } else {
+        minutes
+            .parse::<u64>()
+            .ok()
+            .filter(|minutes| *minutes > 0)
+            .and_then(|minutes| minutes.checked_mul(60))
+            .map(std::time::Duration::from_secs)
+            .ok_or_else(|| format!("无效锁定时长: {}", minutes))?
+    };
+    Ok(LockoutSettings {
+        max_failures,
+        duration,
+    })
+}
+
+/// 解析可选的正整数，留空或 0 表示不限制
+fn parse_optional_limit(value: &str, name: &str) -> Result<Option<u32>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse::<u32>()
+        .map(|limit| (limit > 0).then_some(limit))
+        .map_err(|_| format!("无效{}: {}", name, value))
+}
+
+fn parse_session_limits(
+    max_connections: &str,
+    max_per_ip: &str,
+    max_per_user: &str,
+    idle_minutes: &str,
+    max_minutes: &str,
+) -> Result<SessionLimits, String> {
+    let minutes = |minutes: u32| std::time::Duration::from_secs(u64::from(minutes) * 60);
+    Ok(SessionLimits {
+        max_connections: parse_optional_limit(max_connections, "最大连接数")?,
+        max_per_ip: parse_optional_limit(max_per_ip, "单 IP 连接数")?,
+        max_per_user: parse_optional_limit(max_per_user, "单用户连接数")?,
+        idle_timeout: parse_optional_limit(idle_minutes, "空闲超时")?
+            .map(minutes)
+            .unwrap_or(SessionLimits::default().idle_timeout),
+        max_duration: parse_optional_limit(max_minutes, "最长会话时间")?.map(minutes),
+    })
+}
+
+/// 刷新界面上选中的共享文件列表
+fn refresh_selection(app: &AppWindow, files: &[PathBuf]) {
+    let items: Vec<SharedString> = files
+        .iter()
+        .map(|path| path.display().to_string().into())
+        .collect();
+    app.set_selection(ModelRc::new(VecModel::from(items)));
+}
+
+/// 刷新界面上的挂载点列表
+fn refresh_mounts(app: &AppWindow, mounts: &[MountPoint]) {
+    let items: Vec<MountInfo> = mounts
+        .iter()
+        .map(|mount| {
+            let mut summary = mount.target.display().to_string();
+            if mount.read_only {
+                summary.push_str("（只读）");
+            }
+            MountInfo {
+                path: format!("/{}", mount.path.display()).into(),
+                summary: summary.into(),
+            }
+        })
+        .collect();
+    app.set_mounts(ModelRc::new(VecModel::from(items)));
+}
+
+/// 刷新界面上的锁定列表
+fn refresh_lockouts(app: &AppWindow, lockouts: &Lockouts) {
+    let items: Vec<LockoutInfo> = lockouts
+        .list()
+        .into_iter()
+        .map(|lockout| {
+            let label = match &lockout.key {
+                LockoutKey::Ip(ip) => format!("IP {}", ip),
+                LockoutKey::User(username) => format!("用户 {}", username),
+            };
+            LockoutInfo {
+                id: lockout.key.to_string().into(),
+                label: label.into(),
+                summary: format!(
+                    "剩余 {} 分钟，第 {} 次锁定",
+                    lockout.remaining.as_secs().div_ceil(60),
+                    lockout.strikes
+                )
+                .into(),
+            }
+        })
+        .collect();
+    app.set_lockouts(ModelRc::new(VecModel::from(items)));
+}
+
+/// 刷新界面上的访客账户列表
+fn refresh_guests(app: &AppWindow, guests: &GuestStore) {
+    let items: Vec<GuestInfo> = guests
+        .list()
+        .into_iter()
+        .map(|guest| {
+            let mut summary = vec![
+                match guest.account.access {
+                    Access::UploadOnly => "仅上传",
+                    _ => "只读",
+                }
+                .to_string(),
+            ];
+            if let Some(home) = &guest.account.home {
+                summary.push(format!("目录 {}", home));
+            }
+            if !guest.is_active() {
+                summary.push("已失效".to_string());
+            } else {
+                if let Some(max) = guest.max_downloads {
+                    summary.push(format!("剩余 {} 次下载", max - guest.downloads));
+                }
+                if let Some(expires_at) = guest.expires_at {
+                    summary.push(format!("{} 到期", expires_at.format("%m-%d %H:%M")));
+                }
+            }
+            GuestInfo {
+                username: guest.account.username.into(),
+                password: guest.account.password.into(),
+                summary: summary.join("，").into(),
+            }
+        })
+        .collect();
+    app.set_guests(ModelRc::new(VecModel::from(items)));
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let app = AppWindow::new()?;
     let (cmd_tx, mut cmd_rx) = mpsc::channel::<ServerCommand>(32);
-    let current_task: Arc<Mutex<Option<JoinHandle<()>>>> = Arc::new(Mutex::new(None));
+    let current_task: Arc<Mutex<Option<RunningServer>>> = Arc::new(Mutex::new(None));
+    let services = server::Services {
+        throttle: Arc::new(Throttle::new(BandwidthLimits::default())),
+        transfer_log: Arc::new(TransferLog::new(config::log_dir())),
+        hooks: Arc::new(Hooks::load()),
+        policy: Arc::new(Policy::load()),
+        ip_rules: Arc::new(IpRules::load()),
+        guests: Arc::new(GuestStore::new()),
+        lockouts: Arc::new(Lockouts::new()),
+        connections: Arc::new(Connections::new()),
+        share_mode: Access::default(),
+        files: Arc::default(),
+        mounts: Arc::default(),
+        backend: None,
+        overlay: None,
+    };
+    // 界面上选中的共享文件，启动服务器时生效
+    let selection: Rc<RefCell<Vec<PathBuf>>> = Rc::default();
+    // 界面上配置的挂载点，启动服务器时生效
+    let mounts: Rc<RefCell<Vec<MountPoint>>> = Rc::default();
+    app.set_log_dir(services.transfer_log.dir().display().to_string().into());
 
-    // 浏览按钮回调
+    // 浏览按钮回调，存储选择“归档”时选择归档文件
     let app_weak = app.as_weak();
-    app.on_browse_directory(move || {
+    app.on_browse_directory(move |archive: bool| {
         let app_weak = app_weak.clone();
-        let folder = rfd::FileDialog::new().pick_folder();
-        if let Some(path) = folder {
+        let dialog = rfd::FileDialog::new();
+        let picked = if archive {
+            dialog
+                .add_filter("归档", &["zip", "tar", "gz", "tgz"])
+                .pick_file()
+        } else {
+            dialog.pick_folder()
+        };
+        if let Some(path) = picked {
             let path_str = path.display().to_string();
             slint::invoke_from_event_loop(move || {
                 if let Some(app) = app_weak.upgrade() {
@@ -47,15 +332,104 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
+    // 选择要共享的文件和目录，可多选，追加到已选列表
+    let app_weak = app.as_weak();
+    let selected = selection.clone();
+    app.on_pick_files(move |folders: bool| {
+        let app = app_weak.unwrap();
+        let dialog = rfd::FileDialog::new();
+        let picked = if folders {
+            dialog.pick_folders()
+        } else {
+            dialog.pick_files()
+        };
+        let mut files = selected.borrow_mut();
+        for path in picked.unwrap_or_default() {
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+        refresh_selection(&app, &files);
+    });
+
+    let app_weak = app.as_weak();
+    let selected = selection.clone();
+    app.on_clear_selection(move || {
+        let app = app_weak.unwrap();
+        let mut files = selected.borrow_mut();
+        files.clear();
+        refresh_selection(&app, &files);
+    });
+
+    // 选择挂载目录
+    let app_weak = app.as_weak();
+    app.on_browse_mount_target(move || {
+        let app_weak = app_weak.clone();
+        if let Some(path) = rfd::FileDialog::new().pick_folder() {
+            let path_str = path.display().to_string();
+            slint::invoke_from_event_loop(move || {
+                if let Some(app) = app_weak.upgrade() {
+                    app.invoke_set_mount_target(path_str.into());
+                }
+            })
+            .unwrap();
+        }
+    });
+
+    let app_weak = app.as_weak();
+    let mount_list = mounts.clone();
+    app.on_add_mount(
+        move |path: SharedString, target: SharedString, read_only: bool| {
+            let app = app_weak.unwrap();
+            let target = target.trim();
+            let mount = validate_path(target)
+                .and_then(|_| MountPoint::new(&path, PathBuf::from(target), read_only));
+            let mount = match mount {
+                Ok(mount) => mount,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    app.set_info(e.into());
+                    return;
+                }
+            };
+            let mut mounts = mount_list.borrow_mut();
+            if mounts.iter().any(|existing| existing.path == mount.path) {
+                app.set_info("挂载路径已存在".into());
+                return;
+            }
+            app.set_info(format!("已添加挂载点: /{}", mount.path.display()).into());
+            mounts.push(mount);
+            refresh_mounts(&app, &mounts);
+        },
+    );
+
+    let app_weak = app.as_weak();
+    let mount_list = mounts.clone();
+    app.on_remove_mount(move |path: SharedString| {
+        let app = app_weak.unwrap();
+        let mut mounts = mount_list.borrow_mut();
+        let path = Path::new(path.trim_start_matches('/'));
+        mounts.retain(|mount| mount.path != path);
+        refresh_mounts(&app, &mounts);
+    });
+
     // 启动服务器回调
     let app_weak = app.as_weak();
     let task_handle = current_task.clone();
+    let mount_list = mounts.clone();
+    let selected = selection.clone();
     app.on_start_server(
         move |protocol: SharedString,
               username: SharedString,
               password: SharedString,
               port_str: SharedString,
-              directory: SharedString| {
+              directory: SharedString,
+              mode: SharedString,
+              storage: SharedString,
+              memory_limit: SharedString,
+              passphrase: SharedString,
+              encrypt_names: bool,
+              overlay_dir: SharedString| {
             let app = app_weak.unwrap();
             let cmd_tx = cmd_tx.clone();
             let task_handle = task_handle.clone();
@@ -69,40 +443,178 @@ async fn main() -> anyhow::Result<()> {
                 }
             };
 
-            // 目录不能为空
-            let directory = directory.trim().to_string();
-            if directory.is_empty() {
-                eprintln!("目录不能为空");
-                app.set_info("目录不能为空".into());
-                return;
-            }
+            // 内存存储每次启动时新建，停止服务器后内容随之丢弃；归档和远程地址在目录栏中填写。
+            // 读取归档索引可能要解压整个文件，留到后台任务中进行
+            let archive = (storage.as_str() == "归档").then(|| PathBuf::from(directory.trim()));
+            // 启动成功后一并显示的提示
+            let mut notice = None;
+            let backend: Option<Arc<dyn Vfs>> = match storage.as_str() {
+                "内存" => match parse_memory_limit(&memory_limit) {
+                    Ok(limit) => Some(Arc::new(MemoryFs::new(Some(limit)))),
+                    Err(e) => {
+                        app.set_info(e.into());
+                        return;
+                    }
+                },
+                "S3" => match S3Fs::from_url(&directory) {
+                    Ok(bucket) => Some(Arc::new(bucket)),
+                    Err(e) => {
+                        eprintln!("无效的 S3 地址: {}", e);
+                        app.set_info(format!("无效的 S3 地址: {}", e).into());
+                        return;
+                    }
+                },
+                "上游 SFTP" => match SftpFs::from_url(&directory) {
+                    Ok(upstream) => Some(Arc::new(upstream)),
+                    Err(e) => {
+                        eprintln!("无效的 SFTP 地址: {}", e);
+                        app.set_info(format!("无效的 SFTP 地址: {}", e).into());
+                        return;
+                    }
+                },
+                "加密目录" => {
+                    match CryptFs::open(Path::new(directory.trim()), &passphrase, encrypt_names) {
+                        Ok(encrypted) => {
+                            // 是否加密文件名以目录创建时的设置为准，与界面上的选择不同时告诉用户
+                            if encrypted.encrypts_names() != encrypt_names {
+                                notice = Some(if encrypted.encrypts_names() {
+                                    "该加密目录创建时启用了文件名加密，已沿用该设置"
+                                } else {
+                                    "该加密目录创建时未启用文件名加密，已沿用该设置"
+                                });
+                            }
+                            Some(Arc::new(encrypted))
+                        }
+                        Err(e) => {
+                            eprintln!("无法打开加密目录: {}", e);
+                            app.set_info(format!("无法打开加密目录: {}", e).into());
+                            return;
+                        }
+                    }
+                }
+                _ => None,
+            };
 
-            match validate_path(directory.as_str()) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("{}", e);
-                    app.set_info(e.into());
+            // 叠加层：共享目录只读，每个用户的修改保存在叠加层目录中
+            let overlay = if storage.as_str() == "叠加层" {
+                let dir = match overlay_dir.trim() {
+                    "" => config::config_dir().join("overlay"),
+                    dir => PathBuf::from(dir),
+                };
+                match std::fs::create_dir_all(&dir).and_then(|_| dir.canonicalize()) {
+                    Ok(dir) => Some(dir),
+                    Err(e) => {
+                        eprintln!("无法创建叠加层目录: {}", e);
+                        app.set_info(format!("无法创建叠加层目录: {}", e).into());
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+
+            // 选中了文件或使用其他存储时代替共享目录，目前只有 FTP 和 SFTP 支持
+            let files = selected.borrow().clone();
+            let shares_directory = files.is_empty() && backend.is_none() && archive.is_none();
+            let directory = if shares_directory {
+                directory.trim().to_string()
+            } else {
+                String::new()
+            };
+            if shares_directory {
+                // 目录不能为空
+                if directory.is_empty() {
+                    eprintln!("目录不能为空");
+                    app.set_info("目录不能为空".into());
                     return;
                 }
+
+                match validate_path(directory.as_str()) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        app.set_info(e.into());
+                        return;
+                    }
+                }
+            } else if !matches!(protocol.as_str(), "FTP" | "SFTP") {
+                app.set_info("共享文件和其他存储仅支持 FTP 和 SFTP".into());
+                return;
             }
+            if overlay.is_some() && !matches!(protocol.as_str(), "FTP" | "SFTP") {
+                app.set_info("叠加层仅支持 FTP 和 SFTP".into());
+                return;
+            }
+
+            let share_mode = match mode.as_str() {
+                "只读" => Access::ReadOnly,
+                "仅上传" => Access::UploadOnly,
+                _ => Access::ReadWrite,
+            };
+            let mounts = mount_list.borrow().clone();
 
             app.set_server_running(true);
-            app.set_info("服务器运行中".into());
+            match notice {
+                Some(notice) => app.set_info(format!("服务器运行中，{}", notice).into()),
+                None => app.set_info("服务器运行中".into()),
+            }
 
+            // 显示局域网连接地址及二维码
+            let urls = net::connection_urls(protocol.as_str(), username.as_str(), port);
+            app.set_qr_code(
+                urls.first()
+                    .and_then(|url| qr::render(url))
+                    .unwrap_or_default(),
+            );
+            app.set_urls(ModelRc::new(VecModel::from(
+                urls.into_iter().map(SharedString::from).collect::<Vec<_>>(),
+            )));
+
+            let app_weak = app_weak.clone();
             tokio::spawn(async move {
                 // 停止当前正在运行的服务器
                 let mut guard = task_handle.lock().await;
-                if let Some(handle) = guard.take() {
-                    handle.abort();
-                    let _ = handle.await;
+                if let Some(running) = guard.take() {
+                    running.stop().await;
                 }
 
+                let backend = match archive {
+                    Some(path) => {
+                        let opened = tokio::task::spawn_blocking(move || ArchiveFs::open(&path))
+                            .await
+                            .map_err(std::io::Error::other)
+                            .and_then(|opened| opened);
+                        match opened {
+                            Ok(archive) => Some(Arc::new(archive) as Arc<dyn Vfs>),
+                            Err(e) => {
+                                eprintln!("无法打开归档: {}", e);
+                                slint::invoke_from_event_loop(move || {
+                                    if let Some(app) = app_weak.upgrade() {
+                                        app.set_server_running(false);
+                                        app.set_urls(ModelRc::default());
+                                        app.set_qr_code(slint::Image::default());
+                                        app.set_info(format!("无法打开归档: {}", e).into());
+                                    }
+                                })
+                                .unwrap();
+                                return;
+                            }
+                        }
+                    }
+                    None => backend,
+                };
+
                 let cmd = ServerCommand::Start {
                     protocol: protocol.to_string(),
                     username: username.to_string(),
                     password: password.to_string(),
                     port,
                     directory,
+                    share_mode,
+                    files,
+                    mounts,
+                    backend,
+                    overlay,
                 };
                 let _ = cmd_tx.send(cmd).await;
             });
@@ -117,16 +629,186 @@ async fn main() -> anyhow::Result<()> {
         let task_handle = task_handle.clone();
         app.set_server_running(false);
         app.set_info("服务器已停止".into());
+        app.set_urls(ModelRc::default());
+        app.set_qr_code(slint::Image::default());
 
         tokio::spawn(async move {
             let mut guard = task_handle.lock().await;
-            if let Some(handle) = guard.take() {
-                handle.abort();
-                let _ = handle.await;
+            if let Some(running) = guard.take() {
+                running.stop().await;
             }
         });
     });
 
+    // 复制连接地址到剪贴板
+    let app_weak = app.as_weak();
+    app.on_copy_text(move |text: SharedString| {
+        let app = app_weak.unwrap();
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text.as_str()))
+        {
+            Ok(_) => app.set_info(format!("已复制: {}", text).into()),
+            Err(e) => {
+                eprintln!("复制失败: {}", e);
+                app.set_info("复制失败".into());
+            }
+        }
+    });
+
+    // 切换二维码显示的地址
+    let app_weak = app.as_weak();
+    app.on_show_qr(move |url: SharedString| {
+        let app = app_weak.unwrap();
+        app.set_qr_code(qr::render(url.as_str()).unwrap_or_default());
+    });
+
+    // 应用限速设置，运行中的传输立即生效
+    let app_weak = app.as_weak();
+    let throttle = services.throttle.clone();
+    app.on_apply_rate_limits(
+        move |upload: SharedString,
+              download: SharedString,
+              session: SharedString,
+              user: SharedString| {
+            let app = app_weak.unwrap();
+            match parse_bandwidth_limits(&upload, &download, &session, &user) {
+                Ok(limits) => {
+                    throttle.set_limits(limits);
+                    app.set_info("限速设置已应用".into());
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    app.set_info(e.into());
+                }
+            }
+        },
+    );
+
+    // 传输日志开关，运行中修改立即生效
+    let transfer_log = services.transfer_log.clone();
+    app.on_set_transfer_log(move |enabled: bool, json: bool| {
+        transfer_log.set_options(enabled, json);
+    });
+
+    // 创建访客账户，立即可用于所有协议
+    let app_weak = app.as_weak();
+    let guests = services.guests.clone();
+    app.on_create_guest(
+        move |scope: SharedString,
+              access: SharedString,
+              hours: SharedString,
+              downloads: SharedString| {
+            let app = app_weak.unwrap();
+            let (valid_for, max_downloads) = match parse_guest_limits(&hours, &downloads) {
+                Ok(limits) => limits,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    app.set_info(e.into());
+                    return;
+                }
+            };
+            let scope = scope.trim().trim_matches(['/', '\\']).to_string();
+            let access = if access == "仅上传" {
+                Access::UploadOnly
+            } else {
+                Access::ReadOnly
+            };
+            let guest = guests.create(
+                (!scope.is_empty()).then_some(scope),
+                access,
+                valid_for,
+                max_downloads,
+            );
+            app.set_info(format!("已创建访客账户: {}", guest.account.username).into());
+            refresh_guests(&app, &guests);
+        },
+    );
+
+    let app_weak = app.as_weak();
+    let guests = services.guests.clone();
+    app.on_remove_guest(move |username: SharedString| {
+        let app = app_weak.unwrap();
+        guests.remove(&username);
+        refresh_guests(&app, &guests);
+    });
+
+    // 登录失败锁定设置，运行中修改立即生效
+    let app_weak = app.as_weak();
+    let lockouts = services.lockouts.clone();
+    app.on_apply_lockout_settings(move |max_failures: SharedString, minutes: SharedString| {
+        let app = app_weak.unwrap();
+        match parse_lockout_settings(&max_failures, &minutes) {
+            Ok(settings) => {
+                lockouts.set_settings(settings);
+                app.set_info("锁定设置已应用".into());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                app.set_info(e.into());
+            }
+        }
+    });
+
+    // 连接数和会话时长限制，对之后建立的连接生效
+    let app_weak = app.as_weak();
+    let connections = services.connections.clone();
+    app.on_apply_session_limits(
+        move |max_connections: SharedString,
+              max_per_ip: SharedString,
+              max_per_user: SharedString,
+              idle_minutes: SharedString,
+              max_minutes: SharedString| {
+            let app = app_weak.unwrap();
+            match parse_session_limits(
+                &max_connections,
+                &max_per_ip,
+                &max_per_user,
+                &idle_minutes,
+                &max_minutes,
+            ) {
+                Ok(limits) => {
+                    connections.set_limits(limits);
+                    app.set_info("连接限制已应用".into());
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    app.set_info(e.into());
+                }
+            }
+        },
+    );
+
+    let app_weak = app.as_weak();
+    let lockouts = services.lockouts.clone();
+    app.on_clear_lockout(move |id: SharedString| {
+        let app = app_weak.unwrap();
+        lockouts.clear(&id);
+        refresh_lockouts(&app, &lockouts);
+    });
+
+    let app_weak = app.as_weak();
+    let lockouts = services.lockouts.clone();
+    app.on_clear_all_lockouts(move || {
+        let app = app_weak.unwrap();
+        lockouts.clear_all();
+        refresh_lockouts(&app, &lockouts);
+    });
+
+    // 定时刷新访客账户的剩余次数、失效状态和当前锁定列表
+    let refresh_timer = slint::Timer::default();
+    let app_weak = app.as_weak();
+    let guests = services.guests.clone();
+    let lockouts = services.lockouts.clone();
+    refresh_timer.start(
+        slint::TimerMode::Repeated,
+        std::time::Duration::from_secs(10),
+        move || {
+            if let Some(app) = app_weak.upgrade() {
+                refresh_guests(&app, &guests);
+                refresh_lockouts(&app, &lockouts);
+            }
+        },
+    );
+
     // 后台命令处理：启动对应的服务器
     tokio::spawn(async move {
         while let Some(cmd) = cmd_rx.recv().await {
@@ -137,21 +819,87 @@ async fn main() -> anyhow::Result<()> {
                     password,
                     port,
                     directory,
+                    share_mode,
+                    files,
+                    mounts,
+                    backend,
+                    overlay,
                 } => {
                     println!(
-                        "启动 {} 服务器 (用户: {}, 端口: {}, 目录: {})",
-                        protocol, username, port, directory
+                        "启动 {} 服务器 (用户: {}, 端口: {}, 目录: {}, 模式: {:?}, 挂载点: {})",
+                        protocol,
+                        username,
+                        port,
+                        directory,
+                        share_mode,
+                        mounts.len()
                     );
+                    // 由服务器任务在监听成功后注册，任务结束或被中止时撤销
+                    let announcement =
+                        mdns::Announcement::new(&protocol, port, &directory, &username);
+                    let services = server::Services {
+                        hooks: Arc::new(Hooks::load()),
+                        policy: Arc::new(Policy::load()),
+                        ip_rules: Arc::new(IpRules::load()),
+                        share_mode,
+                        files: Arc::new(files),
+                        mounts: Arc::new(mounts),
+                        backend,
+                        overlay,
+                        ..services.clone()
+                    };
                     let task = match protocol.as_str() {
-                        "FTP" => server::run_ftp_server(username, password, port, directory).await,
+                        "FTP" => {
+                            server::run_ftp_server(
+                                username,
+                                password,
+                                port,
+                                directory,
+                                services,
+                                announcement,
+                            )
+                            .await
+                        }
                         "SFTP" => {
-                            server::run_sftp_server(username, password, port, directory).await
+                            server::run_sftp_server(
+                                username,
+                                password,
+                                port,
+                                directory,
+                                services,
+                                announcement,
+                            )
+                            .await
+                        }
+                        "TFTP" => {
+                            server::run_tftp_server(port, directory, services, announcement).await
+                        }
+                        "HTTP" => {
+                            server::run_http_server(
+                                username,
+                                password,
+                                port,
+                                directory,
+                                services,
+                                announcement,
+                            )
+                            .await
+                        }
+                        "WebDAV" => {
+                            server::run_webdav_server(
+                                username,
+                                password,
+                                port,
+                                directory,
+                                services,
+                                announcement,
+                            )
+                            .await
                         }
-                        "TFTP" => server::run_tftp_server(port, directory).await,
                         _ => continue,
                     };
                     let mut guard = current_task.lock().await;
-                    *guard = Some(task);
+                    *guard = Some(RunningServer { task });
                 }
             }
         }
@@ -161,6 +909,18 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// 正在运行的服务器任务，mDNS 广播由任务持有，停止时一并撤销
+struct RunningServer {
+    task: JoinHandle<()>,
+}
+
+impl RunningServer {
+    async fn stop(self) {
+        self.task.abort();
+        let _ = self.task.await;
+    }
+}
+
 enum ServerCommand {
     Start {
         protocol: String,
@@ -168,5 +928,72 @@ enum ServerCommand {
         password: String,
         port: u16,
         directory: String,
+        share_mode: Access,
+        files: Vec<PathBuf>,
+        mounts: Vec<MountPoint>,
+        backend: Option<Arc<dyn Vfs>>,
+        overlay: Option<PathBuf>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_in_kilobytes() {
+        assert_eq!(parse_rate_limit(""), Ok(0));
+        assert_eq!(parse_rate_limit(" 2 "), Ok(2048));
+        let largest = u64::MAX / 1024;
+        assert_eq!(parse_rate_limit(&largest.to_string()), Ok(largest * 1024));
+        assert_eq!(
+            parse_rate_limit(&(largest + 1).to_string()),
+            Err(format!("无效限速值: {}", largest + 1))
+        );
+        assert!(parse_rate_limit("-1").is_err());
+    }
+
+    #[test]
+    fn lockout_settings_in_minutes() {
+        assert_eq!(
+            parse_lockout_settings("", ""),
+            Ok(LockoutSettings::default())
+        );
+        let settings = parse_lockout_settings(" 3 ", " 10 ").unwrap();
+        assert_eq!(settings.max_failures, 3);
+        assert_eq!(settings.duration, std::time::Duration::from_secs(600));
+        assert!(parse_lockout_settings("x", "").is_err());
+        assert!(parse_lockout_settings("", "0").is_err());
+
+        let largest = u64::MAX / 60;
+        let settings = parse_lockout_settings("", &largest.to_string()).unwrap();
+        assert_eq!(settings.duration.as_secs(), largest * 60);
+        assert_eq!(
+            parse_lockout_settings("", &(largest + 1).to_string()).unwrap_err(),
+            format!("无效锁定时长: {}", largest + 1)
+        );
+    }
+
+    #[test]
+    fn memory_limit_in_megabytes() {
+        assert_eq!(
+            parse_memory_limit(""),
+            Ok(DEFAULT_MEMORY_LIMIT_MB * 1024 * 1024)
+        );
+        assert_eq!(parse_memory_limit(" 1 "), Ok(1024 * 1024));
+        assert_eq!(parse_memory_limit("0"), Err("无效内存上限: 0".to_string()));
+        assert!(parse_memory_limit("-1").is_err());
+        assert!(parse_memory_limit("1.5").is_err());
+    }
+
+    #[test]
+    fn memory_limit_rejects_overflow() {
+        let largest = u64::MAX / (1024 * 1024);
+        assert_eq!(
+            parse_memory_limit(&largest.to_string()),
+            Ok(largest * 1024 * 1024)
+        );
+        assert!(parse_memory_limit(&(largest + 1).to_string()).is_err());
+        assert!(parse_memory_limit(&u64::MAX.to_string()).is_err());
+    }
+}
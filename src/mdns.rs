@@ -0,0 +1,113 @@
+use log::{info, warn};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+/// 待广播的服务，服务器监听成功后才调用 [`Announcement::start`] 注册，
+/// 避免端口被占用等启动失败时仍在局域网内广播
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    protocol: String,
+    port: u16,
+    directory: String,
+    username: String,
+}
+
+impl Announcement {
+    pub fn new(protocol: &str, port: u16, directory: &str, username: &str) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            port,
+            directory: directory.to_string(),
+            username: username.to_string(),
+        }
+    }
+
+    /// 开始广播，返回值应由服务器任务持有，任务结束或被中止时随之撤销
+    pub fn start(&self) -> Option<Advertisement> {
+        Advertisement::start(&self.protocol, self.port, &self.directory, &self.username)
+    }
+}
+
+/// 通过 mDNS/DNS-SD 在局域网内广播服务，停止时自动撤销
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Advertisement {
+    fn start(protocol: &str, port: u16, directory: &str, username: &str) -> Option<Self> {
+        let service_type = match protocol {
+            "FTP" => "_ftp._tcp.local.",
+            "SFTP" => "_sftp-ssh._tcp.local.",
+            "TFTP" => "_tftp._udp.local.",
+            "HTTP" => "_http._tcp.local.",
+            "WebDAV" => "_webdav._tcp.local.",
+            _ => return None,
+        };
+
+        let host = local_hostname();
+        let instance_name = format!("FTP-Quick {} on {}", protocol, host);
+        let host_name = format!("{}.local.", host);
+        let share = std::path::Path::new(directory)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let properties = [("path", "/"), ("u", username), ("share", share.as_str())];
+
+        let daemon = match ServiceDaemon::new() {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                warn!("Failed to start mDNS daemon: {}", e);
+                return None;
+            }
+        };
+
+        let service = match ServiceInfo::new(
+            service_type,
+            &instance_name,
+            &host_name,
+            (),
+            port,
+            &properties[..],
+        ) {
+            Ok(service) => service.enable_addr_auto(),
+            Err(e) => {
+                warn!("Invalid mDNS service info: {}", e);
+                let _ = daemon.shutdown();
+                return None;
+            }
+        };
+
+        let fullname = service.get_fullname().to_string();
+        if let Err(e) = daemon.register(service) {
+            warn!("Failed to register mDNS service {}: {}", fullname, e);
+            let _ = daemon.shutdown();
+            return None;
+        }
+
+        info!("mDNS: advertising {} on port {}", fullname, port);
+        Some(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        info!("mDNS: withdrawing {}", self.fullname);
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            warn!("Failed to unregister mDNS service {}: {}", self.fullname, e);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+fn local_hostname() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "ftp-quick".to_string())
+}
@@ -0,0 +1,47 @@
+use std::net::IpAddr;
+
+/// 枚举本机所有非回环地址，IPv4 排在前面，忽略 IPv6 链路本地地址
+pub fn lan_addresses() -> Vec<IpAddr> {
+    let mut addrs: Vec<IpAddr> = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces
+            .into_iter()
+            .filter(|iface| !iface.is_loopback())
+            .map(|iface| iface.ip())
+            .filter(|ip| match ip {
+                IpAddr::V4(_) => true,
+                // fe80::/10 需要带 zone id，不适合直接放在 URL 中
+                IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) != 0xfe80,
+            })
+            .collect(),
+        Err(e) => {
+            log::warn!("Failed to enumerate network interfaces: {}", e);
+            Vec::new()
+        }
+    };
+    addrs.sort_by_key(|ip| ip.is_ipv6());
+    addrs.dedup();
+    addrs
+}
+
+/// 生成客户端可直接使用的连接地址，例如 `ftp://user@192.168.1.10:21/`
+///
+/// TFTP 没有用户名；浏览器会对带用户名的 HTTP 地址发出警告，也不附带用户名。
+/// WebDAV 使用 `http://` 地址，大多数客户端可直接挂载
+pub fn connection_urls(protocol: &str, username: &str, port: u16) -> Vec<String> {
+    let scheme = match protocol {
+        "WebDAV" => "http".to_string(),
+        _ => protocol.to_lowercase(),
+    };
+    let user = if username.is_empty() || matches!(protocol, "TFTP" | "HTTP" | "WebDAV") {
+        String::new()
+    } else {
+        format!("{}@", username)
+    };
+    lan_addresses()
+        .into_iter()
+        .map(|ip| match ip {
+            IpAddr::V4(v4) => format!("{}://{}{}:{}/", scheme, user, v4, port),
+            IpAddr::V6(v6) => format!("{}://{}[{}]:{}/", scheme, user, v6, port),
+        })
+        .collect()
+}
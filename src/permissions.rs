@@ -0,0 +1,258 @@
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::accounts::Access;
+use crate::vfs::normalize;
+
+/// 可单独授予的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// 列出目录
+    List,
+    Download,
+    /// 上传新文件
+    Upload,
+    /// 覆盖或续传已有文件
+    Overwrite,
+    Delete,
+    Rename,
+    Mkdir,
+    Rmdir,
+    /// 修改文件权限位
+    Chmod,
+}
+
+impl Permission {
+    const ALL: [Permission; 9] = [
+        Permission::List,
+        Permission::Download,
+        Permission::Upload,
+        Permission::Overwrite,
+        Permission::Delete,
+        Permission::Rename,
+        Permission::Mkdir,
+        Permission::Rmdir,
+        Permission::Chmod,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Permission::List => "list",
+            Permission::Download => "download",
+            Permission::Upload => "upload",
+            Permission::Overwrite => "overwrite",
+            Permission::Delete => "delete",
+            Permission::Rename => "rename",
+            Permission::Mkdir => "mkdir",
+            Permission::Rmdir => "rmdir",
+            Permission::Chmod => "chmod",
+        }
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.name() == s)
+            .ok_or_else(|| format!("unknown permission {:?}", s))
+    }
+}
+
+/// 一组权限
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions(u16);
+
+impl Permissions {
+    pub const ALL: Permissions = Permissions((1 << Permission::ALL.len()) - 1);
+
+    pub fn of(permissions: &[Permission]) -> Self {
+        Self(
+            permissions
+                .iter()
+                .fold(0, |bits, permission| bits | permission.bit()),
+        )
+    }
+
+    pub fn contains(self, permission: Permission) -> bool {
+        self.0 & permission.bit() != 0
+    }
+
+    /// 两组权限都包含的权限，例如共享模式与账户在某路径上的权限同时生效
+    pub fn intersect(self, other: Permissions) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl FromStr for Permissions {
+    type Err = String;
+
+    /// 逗号分隔的权限名，`*` 表示全部，空串表示没有任何权限
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut permissions = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if name == "*" {
+                return Ok(Self::ALL);
+            }
+            permissions.push(name.parse()?);
+        }
+        Ok(Self::of(&permissions))
+    }
+}
+
+/// 单条路径规则，例如 `/incoming: upload,mkdir`，作用于该目录及其下所有内容
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PathRule {
+    path: PathBuf,
+    permissions: Permissions,
+}
+
+impl FromStr for PathRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, permissions) = s
+            .split_once(':')
+            .ok_or_else(|| format!("missing ':' in permission rule {:?}", s))?;
+        Ok(Self {
+            path: normalize(Path::new(path.trim())),
+            permissions: permissions
+                .parse()
+                .map_err(|e| format!("{} in permission rule {:?}", e, s))?,
+        })
+    }
+}
+
+impl TryFrom<String> for PathRule {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// 账户的路径权限规则，路径相对于账户主目录
+///
+/// 最长匹配的规则生效，没有规则匹配的路径按账户的 `access` 授权：
+///
+/// ```json
+/// "permissions": ["/: list,download", "/incoming: upload,mkdir"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct PathRules(Vec<PathRule>);
+
+impl PathRules {
+    /// 账户在该路径上拥有的权限
+    pub fn resolve(&self, access: Access, path: &Path) -> Permissions {
+        let path = normalize(path);
+        self.0
+            .iter()
+            .filter(|rule| path.starts_with(&rule.path))
+            .max_by_key(|rule| rule.path.components().count())
+            .map_or_else(|| access.permissions(), |rule| rule.permissions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> PathRules {
+        PathRules(rules.iter().map(|rule| rule.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn parses_permission_lists() {
+        assert_eq!("*".parse(), Ok(Permissions::ALL));
+        assert_eq!("".parse(), Ok(Permissions::default()));
+        assert_eq!(
+            " list , download ".parse(),
+            Ok(Permissions::of(&[Permission::List, Permission::Download]))
+        );
+        assert!("list,fly".parse::<Permissions>().is_err());
+        assert!("/incoming upload".parse::<PathRule>().is_err());
+    }
+
+    #[test]
+    fn longest_matching_rule_wins() {
+        let rules = rules(&[
+            "/: list,download",
+            "/incoming: upload,mkdir",
+            "/incoming/private:",
+        ]);
+        let at = |path: &str| rules.resolve(Access::ReadWrite, Path::new(path));
+        assert_eq!(
+            at("/docs/a.txt"),
+            Permissions::of(&[Permission::List, Permission::Download])
+        );
+        assert_eq!(
+            at("incoming/a.txt"),
+            Permissions::of(&[Permission::Upload, Permission::Mkdir])
+        );
+        assert_eq!(at("/incoming/private/a.txt"), Permissions::default());
+        // 按路径组件匹配，不按字符串前缀
+        assert_eq!(at("/incoming-old"), at("/docs"));
+        // `..` 先规范化再匹配
+        assert_eq!(at("/incoming/private/../a.txt"), at("/incoming"));
+    }
+
+    #[test]
+    fn unmatched_paths_use_access() {
+        let rules = rules(&["/incoming: upload"]);
+        assert_eq!(
+            rules.resolve(Access::ReadOnly, Path::new("/docs")),
+            Access::ReadOnly.permissions()
+        );
+        assert_eq!(
+            PathRules::default().resolve(Access::UploadOnly, Path::new("/")),
+            Access::UploadOnly.permissions()
+        );
+        // 规则可以授予超出 `access` 的权限
+        assert!(
+            rules
+                .resolve(Access::ReadOnly, Path::new("/incoming"))
+                .contains(Permission::Upload)
+        );
+    }
+
+    #[test]
+    fn share_mode_limits_rules() {
+        let rules = rules(&["/: *", "/incoming: upload,overwrite,delete"]);
+        let granted = rules.resolve(Access::ReadWrite, Path::new("/incoming/a.txt"));
+        let effective = Access::ReadOnly.permissions().intersect(granted);
+        assert_eq!(effective, Permissions::default());
+
+        let effective = Access::UploadOnly.permissions().intersect(granted);
+        assert_eq!(
+            effective,
+            Permissions::of(&[Permission::Upload, Permission::Overwrite])
+        );
+
+        let granted = rules.resolve(Access::ReadWrite, Path::new("/docs"));
+        assert_eq!(
+            Access::ReadOnly.permissions().intersect(granted),
+            Access::ReadOnly.permissions()
+        );
+        assert_eq!(
+            Access::ReadWrite.permissions().intersect(granted),
+            Permissions::ALL
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{info, warn};
+use rhai::{AST, Dynamic, Engine, FuncArgs, Scope};
+
+use crate::config::config_dir;
+
+/// 策略脚本文件名，位于配置目录下
+pub const POLICY_FILE: &str = "policy.rhai";
+
+/// 单次调用允许执行的最大操作数，防止脚本死循环卡住会话
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// 上传过程中每写入这么多字节再询问一次策略脚本，脚本在异步任务中同步执行，不能每块都调用
+const UPLOAD_CHECK_INTERVAL: u64 = 1024 * 1024;
+
+/// 策略脚本对一次操作的裁决
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny,
+    /// 改用脚本返回的路径
+    Rewrite(String),
+}
+
+impl Decision {
+    pub fn is_denied(&self) -> bool {
+        *self == Decision::Deny
+    }
+
+    /// 应用裁决后的路径，拒绝时为 `None`
+    pub fn apply(self, path: String) -> Option<String> {
+        match self {
+            Decision::Allow => Some(path),
+            Decision::Deny => None,
+            Decision::Rewrite(path) => Some(path),
+        }
+    }
+}
+
+/// 用 Rhai 编写的认证与传输策略
+///
+/// 在 `config/policy.rhai` 中定义以下任意函数，未定义的函数视为允许：
+///
+/// ```rhai
+/// fn authorize(user, ip, protocol) { ip.starts_with("192.168.") }
+/// fn on_upload(path, size) { if size > 100 * 1024 * 1024 { false } else { "/incoming" + path } }
+/// fn on_download(path) { !path.ends_with(".key") }
+/// ```
+///
+/// 不返回值或返回 `true` 表示允许，返回 `false` 表示拒绝，返回字符串表示改写路径，
+/// 其余返回值视为拒绝。
+/// 路径为客户端看到的路径，`size` 为目前已写入的字节数：打开文件时为 0，
+/// 之后每写入 1 MiB 和上传结束时各调用一次，见 [`UploadCheck`]。
+#[derive(Debug)]
+pub struct Policy {
+    engine: Engine,
+    ast: Option<AST>,
+    /// 脚本无法读取或编译时拒绝所有操作，避免策略静默失效
+    deny_all: bool,
+}
+
+impl Policy {
+    pub fn load() -> Self {
+        let path = config_dir().join(POLICY_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(script) => {
+                let policy = Self::from_script(&script);
+                if !policy.deny_all {
+                    info!("Loaded policy script {:?}", path);
+                }
+                policy
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::empty(),
+            Err(e) => {
+                warn!("Failed to read {:?}, denying all operations: {}", path, e);
+                Self::deny_all()
+            }
+        }
+    }
+
+    /// 编译脚本，编译失败时拒绝所有操作，直到脚本修正后重新加载
+    fn from_script(script: &str) -> Self {
+        let policy = Self::empty();
+        match policy.engine.compile(script) {
+            Ok(ast) => Self {
+                ast: Some(ast),
+                ..policy
+            },
+            Err(e) => {
+                warn!(
+                    "Failed to compile {}, denying all operations: {}",
+                    POLICY_FILE, e
+                );
+                Self::deny_all()
+            }
+        }
+    }
+
+    fn empty() -> Self {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("policy: {}", text));
+        Self {
+            engine,
+            ast: None,
+            deny_all: false,
+        }
+    }
+
+    fn deny_all() -> Self {
+        Self {
+            deny_all: true,
+            ..Self::empty()
+        }
+    }
+
+    /// 登录时在账户校验通过后调用，返回是否允许登录
+    pub fn authorize(&self, user: &str, ip: &str, protocol: &str) -> bool {
+        !self
+            .call(
+                "authorize",
+                (user.to_string(), ip.to_string(), protocol.to_string()),
+            )
+            .is_denied()
+    }
+
+    pub fn on_upload(&self, path: &str, size: u64) -> Decision {
+        self.call("on_upload", (path.to_string(), size as i64))
+    }
+
+    pub fn on_download(&self, path: &str) -> Decision {
+        self.call("on_download", (path.to_string(),))
+    }
+
+    fn call(&self, name: &str, args: impl FuncArgs) -> Decision {
+        if self.deny_all {
+            return Decision::Deny;
+        }
+        let Some(ast) = &self.ast else {
+            return Decision::Allow;
+        };
+        if !ast.iter_functions().any(|function| function.name == name) {
+            return Decision::Allow;
+        }
+
+        match self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), ast, name, args)
+        {
+            Ok(result) => Self::decision(name, result),
+            Err(e) => {
+                // 脚本出错时拒绝，避免策略失效后放行
+                warn!("Policy function {} failed: {}", name, e);
+                Decision::Deny
+            }
+        }
+    }
+
+    fn decision(name: &str, result: Dynamic) -> Decision {
+        if result.is_unit() {
+            return Decision::Allow;
+        }
+        if let Ok(allowed) = result.as_bool() {
+            return if allowed {
+                Decision::Allow
+            } else {
+                Decision::Deny
+            };
+        }
+        if result.is_string() {
+            return Decision::Rewrite(result.into_string().unwrap_or_default());
+        }
+        warn!(
+            "Policy function {} returned unsupported type {}",
+            name,
+            result.type_name()
+        );
+        Decision::Deny
+    }
+}
+
+/// 上传过程中按已写入的大小再次询问策略脚本，各协议共用
+///
+/// 文件已经按打开时的裁决打开，之后拒绝或改写到其他路径都视为拒绝，调用方应丢弃已写入的内容。
+#[derive(Debug)]
+pub struct UploadCheck {
+    policy: Arc<Policy>,
+    /// 策略改写前客户端请求的路径
+    requested: String,
+    /// 打开时裁决的路径
+    path: PathBuf,
+    /// 目前写入到的最大长度
+    size: u64,
+    /// 上次询问时的长度
+    checked: u64,
+}
+
+impl UploadCheck {
+    /// `size` 为打开时已询问过的长度，续传时为续传的起点
+    pub fn new(policy: Arc<Policy>, requested: String, path: PathBuf, size: u64) -> Self {
+        Self {
+            policy,
+            requested,
+            path,
+            size,
+            checked: size,
+        }
+    }
+
+    /// 写入到 `size` 字节后调用，返回是否允许继续
+    pub fn wrote(&mut self, size: u64) -> bool {
+        self.size = self.size.max(size);
+        if self.size < self.checked.saturating_add(UPLOAD_CHECK_INTERVAL) {
+            return true;
+        }
+        self.check()
+    }
+
+    /// 上传结束时按最终大小再询问一次
+    pub fn finish(&mut self) -> bool {
+        self.size == self.checked || self.check()
+    }
+
+    fn check(&mut self) -> bool {
+        self.checked = self.size;
+        let allowed = match self.policy.on_upload(&self.requested, self.size) {
+            Decision::Allow => true,
+            Decision::Deny => false,
+            Decision::Rewrite(rewritten) => Path::new(&rewritten) == self.path,
+        };
+        if !allowed {
+            warn!(
+                "Upload of {:?} denied by policy at {} bytes",
+                self.path, self.size
+            );
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_functions_allow() {
+        let policy = Policy::empty();
+        assert!(policy.authorize("alice", "192.168.1.2", "ftp"));
+        assert_eq!(policy.on_upload("/a.txt", 0), Decision::Allow);
+
+        let policy = Policy::from_script("fn on_download(path) { false }");
+        assert_eq!(policy.on_upload("/a.txt", 0), Decision::Allow);
+        assert_eq!(policy.on_download("/a.txt"), Decision::Deny);
+    }
+
+    #[test]
+    fn broken_script_denies_everything() {
+        let policy = Policy::from_script("fn on_download(path) { !path.ends_with(\".key\") ");
+        assert!(!policy.authorize("alice", "192.168.1.2", "ftp"));
+        assert_eq!(policy.on_upload("/a.txt", 0), Decision::Deny);
+        assert_eq!(policy.on_download("/a.txt"), Decision::Deny);
+
+        let policy = Policy::from_script("fn on_download(path) { !path.ends_with(\".key\") }");
+        assert_eq!(policy.on_download("/a.txt"), Decision::Allow);
+        assert_eq!(policy.on_download("/a.key"), Decision::Deny);
+    }
+
+    #[test]
+    fn return_values() {
+        let policy = Policy::from_script(
+            r#"
+            fn on_upload(path, size) {
+                if size > 100 { return false; }
+                if path.ends_with(".bin") { return "/firmware" + path; }
+                if path.ends_with(".txt") { return true; }
+                if path.ends_with(".num") { return 1; }
+            }
+            "#,
+        );
+        assert_eq!(policy.on_upload("/a", 0), Decision::Allow);
+        assert_eq!(policy.on_upload("/a.txt", 0), Decision::Allow);
+        assert_eq!(policy.on_upload("/a.txt", 101), Decision::Deny);
+        assert_eq!(
+            policy.on_upload("/a.bin", 0),
+            Decision::Rewrite("/firmware/a.bin".to_string())
+        );
+        assert_eq!(policy.on_upload("/a.num", 0), Decision::Deny);
+    }
+
+    #[test]
+    fn upload_check_runs_per_interval_and_at_the_end() {
+        let policy = Arc::new(Policy::from_script(
+            "fn on_upload(path, size) { if size > 1500000 { false } else { \"/in\" + path } }",
+        ));
+        let mut check = UploadCheck::new(policy.clone(), "/a".to_string(), "/in/a".into(), 0);
+        // 未达到间隔时不询问，因此超过限制的部分要到下一次询问时才发现
+        assert!(check.wrote(UPLOAD_CHECK_INTERVAL - 1));
+        assert!(check.wrote(UPLOAD_CHECK_INTERVAL));
+        assert!(check.wrote(1_600_000));
+        assert!(!check.wrote(2 * UPLOAD_CHECK_INTERVAL));
+
+        // 续传从已询问过的长度开始计算间隔
+        let mut check =
+            UploadCheck::new(policy.clone(), "/a".to_string(), "/in/a".into(), 1_000_000);
+        assert!(check.wrote(1_600_000));
+        assert!(!check.finish());
+
+        let script = "fn on_upload(path, size) { size < 1000000 }";
+        let mut check = UploadCheck::new(
+            Arc::new(Policy::from_script(script)),
+            "/a".to_string(),
+            "/a".into(),
+            0,
+        );
+        assert!(check.wrote(1_000_000));
+        assert!(!check.finish());
+
+        let mut check = UploadCheck::new(policy.clone(), "/a".to_string(), "/in/a".into(), 0);
+        assert!(check.wrote(100));
+        assert!(check.finish());
+
+        // 改写到打开时以外的路径视为拒绝
+        let mut check = UploadCheck::new(policy, "/a".to_string(), "/a".into(), 0);
+        assert!(check.finish());
+        assert!(check.wrote(100));
+        assert!(!check.finish());
+    }
+
+    #[test]
+    fn runtime_errors_deny() {
+        let policy =
+            Policy::from_script("fn authorize(user, ip, protocol) { undefined_function() }");
+        assert!(!policy.authorize("alice", "192.168.1.2", "ftp"));
+        let policy = Policy::from_script("fn on_download(path) { loop {} }");
+        assert_eq!(policy.on_download("/a.txt"), Decision::Deny);
+    }
+}
@@ -0,0 +1,40 @@
+use qrcode::{Color, QrCode};
+use slint::{Image, Rgb8Pixel, SharedPixelBuffer};
+
+/// 每个模块绘制的像素数
+const MODULE_SIZE: usize = 4;
+/// 四周留白的模块数
+const QUIET_ZONE: usize = 4;
+
+/// 将文本渲染为二维码图片，供 Slint 界面显示
+pub fn render(text: &str) -> Option<Image> {
+    let code = match QrCode::new(text.as_bytes()) {
+        Ok(code) => code,
+        Err(e) => {
+            log::warn!("Failed to encode QR code for {}: {:?}", text, e);
+            return None;
+        }
+    };
+
+    let width = code.width();
+    let colors = code.to_colors();
+    let side = (width + QUIET_ZONE * 2) * MODULE_SIZE;
+
+    let mut buffer = SharedPixelBuffer::<Rgb8Pixel>::new(side as u32, side as u32);
+    let pixels = buffer.make_mut_slice();
+    for (y, row) in pixels.chunks_mut(side).enumerate() {
+        let my = y / MODULE_SIZE;
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let mx = x / MODULE_SIZE;
+            let dark = mx >= QUIET_ZONE
+                && my >= QUIET_ZONE
+                && mx < QUIET_ZONE + width
+                && my < QUIET_ZONE + width
+                && colors[(my - QUIET_ZONE) * width + (mx - QUIET_ZONE)] == Color::Dark;
+            let value = if dark { 0 } else { 255 };
+            *pixel = Rgb8Pixel::new(value, value, value);
+        }
+    }
+
+    Some(Image::from_rgb8(buffer))
+}
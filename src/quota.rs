@@ -0,0 +1,60 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 磁盘配额，`None` 表示不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+/// 目录当前占用
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+pub const QUOTA_EXCEEDED: &str = "Quota exceeded";
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes.is_none() && self.max_files.is_none()
+    }
+
+    /// 能否再创建一个新文件
+    pub fn allows_new_file(&self, usage: &Usage) -> bool {
+        self.max_files.is_none_or(|max| usage.files < max)
+            && self.max_bytes.is_none_or(|max| usage.bytes < max)
+    }
+
+    /// 在当前占用下还可写入的字节数
+    pub fn remaining_bytes(&self, usage: &Usage) -> Option<u64> {
+        self.max_bytes.map(|max| max.saturating_sub(usage.bytes))
+    }
+}
+
+/// 统计目录下所有文件的总大小和数量（不跟随符号链接）
+pub async fn usage(dir: PathBuf) -> io::Result<Usage> {
+    tokio::task::spawn_blocking(move || {
+        let mut usage = Usage::default();
+        walk(&dir, &mut usage)?;
+        Ok(usage)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+fn walk(dir: &Path, usage: &mut Usage) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), usage)?;
+        } else if file_type.is_file() {
+            usage.files += 1;
+            usage.bytes += entry.metadata()?.len();
+        }
+    }
+    Ok(())
+}
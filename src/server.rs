@@ -1,15 +1,90 @@
+use axum::Extension;
+use axum::extract::ConnectInfo;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
 use libunftp::ServerBuilder;
-use log::{LevelFilter, error, info};
-use std::path::PathBuf;
-use tokio::task::JoinHandle;
+use log::{LevelFilter, error, info, warn};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::net::TcpListener;
+use tokio::task::{JoinHandle, JoinSet};
+use unftp_core::auth::UserDetailProvider;
+
+use crate::accounts::{Access, AccountStore};
+use crate::acl::IpRules;
+use crate::audit::TransferLog;
+use crate::ftp::{AccountAuthenticator, FtpStorage, FtpUser, FtpUserProvider};
+use crate::guests::GuestStore;
+use crate::hooks::Hooks;
+use crate::limits::{Connections, with_max_duration};
+use crate::lockout::{Lockouts, MAX_AUTH_ATTEMPTS};
+use crate::mdns::Announcement;
+use crate::permissions::{Permission, Permissions};
+use crate::policy::Policy;
+use crate::throttle::Throttle;
+use crate::vfs::{MountPoint, Vfs};
 
 pub(crate) use crate::ssh::server::{Server, ServerConfig};
 use russh::keys::ssh_key::rand_core::OsRng;
 use russh::keys::{Algorithm, PrivateKey};
 use russh::server::Server as _;
+use russh::{Disconnect, MethodKind, MethodSet};
 use std::sync::Arc;
 use std::time::Duration;
 
+/// 各协议共享的运行时服务，可在服务器运行期间从界面实时调整
+#[derive(Debug, Clone)]
+pub struct Services {
+    pub throttle: Arc<Throttle>,
+    pub transfer_log: Arc<TransferLog>,
+    /// 钩子、策略脚本和 IP 规则在每次启动服务器时从配置目录重新加载
+    pub hooks: Arc<Hooks>,
+    pub policy: Arc<Policy>,
+    pub ip_rules: Arc<IpRules>,
+    pub guests: Arc<GuestStore>,
+    pub lockouts: Arc<Lockouts>,
+    pub connections: Arc<Connections>,
+    /// 启动服务器时在界面上选择的共享模式，与账户的访问方式同时生效
+    pub share_mode: Access,
+    /// 界面上选中的文件和目录，非空时代替共享目录平铺在根目录下，仅用于 FTP 和 SFTP
+    pub files: Arc<Vec<PathBuf>>,
+    /// 启动服务器时界面上配置的挂载点，FTP 和 SFTP 中可访问整个共享的账户可见
+    pub mounts: Arc<Vec<MountPoint>>,
+    /// 代替共享目录的存储后端，例如内存存储，仅用于 FTP 和 SFTP
+    pub backend: Option<Arc<dyn Vfs>>,
+    /// 叠加层目录，设置后共享内容只读，每个用户的修改保存在其中以用户名命名的子目录，仅用于 FTP 和 SFTP
+    pub overlay: Option<PathBuf>,
+}
+
+impl Services {
+    /// 能否进行该操作：检查共享模式、账户在该路径上的权限，以及访客账户是否已失效
+    pub fn permits(&self, username: &str, granted: Permissions, permission: Permission) -> bool {
+        self.share_mode
+            .permissions()
+            .intersect(granted)
+            .contains(permission)
+            && self.guests.is_active(username)
+    }
+
+    /// 全局 IP 规则是否允许该客户端连接，拒绝时记录日志
+    pub fn accepts(&self, protocol: &str, peer: SocketAddr) -> bool {
+        let accepted = self.ip_rules.permits(peer.ip());
+        if !accepted {
+            warn!(
+                "Refused {} connection from {}: blocked by IP rules",
+                protocol, peer
+            );
+        }
+        accepted
+    }
+
+    /// 共享的内容是否来自界面上填写的目录，而不是选中的文件或其他存储后端
+    pub fn shares_directory(&self) -> bool {
+        self.files.is_empty() && self.backend.is_none()
+    }
+}
+
 // ---------- FTP 服务器 ----------
 
 pub async fn run_ftp_server(
@@ -17,64 +92,133 @@ pub async fn run_ftp_server(
     password: String,
     port: u16,
     directory: String,
+    services: Services,
+    announcement: Announcement,
 ) -> JoinHandle<()> {
-    use unftp_sbe_fs::Filesystem;
-    let user_pass_json = format!(
-        "[{{\"username\": \"{}\",\"password\": \"{}\"}}]",
-        username, password
-    );
     let ftp_home = PathBuf::from(directory);
-    let authenticator =
-        Arc::new(unftp_auth_jsonfile::JsonFileAuthenticator::from_json(user_pass_json).unwrap());
-    let server = if username.len() > 0 && password.len() > 0 {
-        ServerBuilder::with_authenticator(
-            Box::new(move || Filesystem::new(ftp_home.clone()).unwrap()),
-            authenticator,
-        )
-    } else {
-        ServerBuilder::new(Box::new(move || Filesystem::new(ftp_home.clone()).unwrap()))
-    }
-    .greeting("Welcome to my FTP server")
-    .passive_ports(50000..=65535)
-    .build()
-    .unwrap();
+    let accounts = Arc::new(AccountStore::load(
+        &username,
+        &password,
+        services.guests.clone(),
+    ));
     tokio::spawn(async move {
-        if let Err(e) = server.listen(format!("0.0.0.0:{port}")).await {
-            eprintln!("ftp server error: {:?}", e);
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("ftp server error: {:?}", e);
+                return;
+            }
+        };
+        let _advertisement = announcement.start();
+        // 会话随接受任务一起中止，停止或重启服务器时断开所有客户端
+        let mut sessions = JoinSet::new();
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("Failed to accept FTP connection: {}", e);
+                    continue;
+                }
+            };
+            while sessions.try_join_next().is_some() {}
+            if !services.accepts("ftp", peer) {
+                continue;
+            }
+            let Some(connection) = services.connections.open("ftp", peer) else {
+                continue;
+            };
+            // 每个控制连接单独构建服务器，使存储后端知道客户端地址
+            let server = build_ftp_server(&ftp_home, &accounts, &services, peer);
+            let max_duration = services.connections.limits().max_duration;
+            sessions.spawn(async move {
+                let _connection = connection;
+                match with_max_duration(max_duration, server.service(stream)).await {
+                    Some(Ok(())) => {}
+                    Some(Err(e)) => eprintln!("ftp session error: {:?}", e),
+                    None => info!("FTP session from {} reached the maximum duration", peer),
+                }
+            });
         }
     })
 }
 
+fn build_ftp_server(
+    ftp_home: &Path,
+    accounts: &Arc<AccountStore>,
+    services: &Services,
+    peer: SocketAddr,
+) -> libunftp::Server<FtpStorage, FtpUser> {
+    let authenticator = Arc::new(AccountAuthenticator::new(
+        accounts.clone(),
+        services.clone(),
+        peer,
+    ));
+    let provider: Arc<dyn UserDetailProvider<User = FtpUser> + Send + Sync> = Arc::new(
+        FtpUserProvider::new(accounts.clone(), ftp_home.to_path_buf()),
+    );
+    let ftp_home = ftp_home.to_path_buf();
+    let services = services.clone();
+    ServerBuilder::<FtpStorage, FtpUser>::with_user_detail_provider(
+        Box::new(move || FtpStorage::new(ftp_home.clone(), &services, peer)),
+        provider,
+    )
+    .authenticator(authenticator)
+    .greeting("Welcome to my FTP server")
+    .passive_ports(50000..=65535)
+    .idle_session_timeout(services.connections.limits().idle_timeout.as_secs())
+    .build()
+    .unwrap()
+}
+
 pub async fn run_sftp_server(
     username: String,
     password: String,
     port: u16,
     directory: String,
+    services: Services,
+    announcement: Announcement,
 ) -> JoinHandle<()> {
-    use std::path::Path;
     env_logger::builder().filter_level(LevelFilter::Info).init();
     let root_dir = Path::new(&directory);
 
-    if !root_dir.exists() {
-        error!("Root directory {:?} does not exist", directory);
-        std::process::exit(1);
-    }
+    // 共享选中的文件或使用其他存储后端时没有根目录
+    if services.shares_directory() {
+        if !root_dir.exists() {
+            error!("Root directory {:?} does not exist", directory);
+            std::process::exit(1);
+        }
 
-    if !root_dir.is_dir() {
-        error!("Root directory {:?} is not a directory", directory);
-        std::process::exit(1);
+        if !root_dir.is_dir() {
+            error!("Root directory {:?} is not a directory", directory);
+            std::process::exit(1);
+        }
     }
 
     let server_config = Arc::new(ServerConfig {
-        username,
-        password,
+        accounts: Arc::new(AccountStore::load(
+            &username,
+            &password,
+            services.guests.clone(),
+        )),
         root_dir: root_dir.to_path_buf(),
         max_read_size: 32768,
+        services,
     });
 
+    // 有账户时只提供密码认证，客户端不会再逐个尝试公钥
+    let methods = if server_config.accounts.is_anonymous() {
+        MethodSet::all()
+    } else {
+        MethodSet::from(&[MethodKind::Password][..])
+    };
     let config = russh::server::Config {
+        methods,
         auth_rejection_time: Duration::from_secs(3),
         auth_rejection_time_initial: Some(Duration::from_secs(0)),
+        max_auth_attempts: MAX_AUTH_ATTEMPTS as usize,
+        // 客户端对 keepalive 的响应会重置空闲计时，因此不启用 keepalive
+        inactivity_timeout: Some(server_config.services.connections.limits().idle_timeout),
+        keepalive_interval: None,
         keys: vec![
             PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap(),
             PrivateKey::random(&mut OsRng, Algorithm::Rsa { hash: None }).unwrap(),
@@ -86,28 +230,212 @@ pub async fn run_sftp_server(
     let mut server = Server {
         config: server_config,
     };
+    let config = Arc::new(config);
 
     info!("Starting SFTP server on 0.0.0.0:{}", port);
-    info!(
-        "Use credentials: username='{}', password='***'",
-        server.config.username
-    );
+    info!("Use credentials: username='{}', password='***'", username);
     tokio::spawn(async move {
-        server
-            .run_on_address(Arc::new(config), ("0.0.0.0", port))
-            .await
-            .unwrap();
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("sftp server error: {:?}", e);
+                return;
+            }
+        };
+        let _advertisement = announcement.start();
+        // 会话随接受任务一起中止，停止或重启服务器时断开所有客户端
+        let mut sessions = JoinSet::new();
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("Failed to accept SFTP connection: {}", e);
+                    continue;
+                }
+            };
+            while sessions.try_join_next().is_some() {}
+            let services = &server.config.services;
+            if !services.accepts("sftp", peer) {
+                continue;
+            }
+            let Some(connection) = services.connections.open("sftp", peer) else {
+                continue;
+            };
+            let max_duration = services.connections.limits().max_duration;
+            let handler = server.new_client(Some(peer));
+            let config = config.clone();
+            sessions.spawn(async move {
+                let _connection = connection;
+                let session = match russh::server::run_stream(config, stream, handler).await {
+                    Ok(session) => session,
+                    Err(e) => {
+                        warn!("SFTP connection setup with {} failed: {}", peer, e);
+                        return;
+                    }
+                };
+                let handle = session.handle();
+                let _abort = DisconnectOnAbort(handle.clone());
+                match with_max_duration(max_duration, session).await {
+                    Some(Ok(())) => {}
+                    Some(Err(e)) => warn!("SFTP session from {} closed with error: {}", peer, e),
+                    None => {
+                        info!("SFTP session from {} reached the maximum duration", peer);
+                        let _ = handle
+                            .disconnect(
+                                Disconnect::ByApplication,
+                                "Session time limit reached".to_string(),
+                                String::new(),
+                            )
+                            .await;
+                    }
+                }
+            });
+        }
+    })
+}
+
+/// russh 在自己的任务中运行会话，等待它的任务被中止时需要主动断开客户端
+struct DisconnectOnAbort(russh::server::Handle);
+
+impl Drop for DisconnectOnAbort {
+    fn drop(&mut self) {
+        // 会话已正常结束时断开请求会直接失败
+        let handle = self.0.clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = handle
+                    .disconnect(
+                        Disconnect::ByApplication,
+                        "Server stopped".to_string(),
+                        String::new(),
+                    )
+                    .await;
+            });
+        }
+    }
+}
+
+// ---------- HTTP 服务器 ----------
+
+pub async fn run_http_server(
+    username: String,
+    password: String,
+    port: u16,
+    directory: String,
+    services: Services,
+    announcement: Announcement,
+) -> JoinHandle<()> {
+    use crate::http::HttpState;
+
+    let state = Arc::new(HttpState {
+        protocol: "http",
+        root: PathBuf::from(directory),
+        accounts: Arc::new(AccountStore::load(
+            &username,
+            &password,
+            services.guests.clone(),
+        )),
+        services,
+    });
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("http server error: {:?}", e);
+                return;
+            }
+        };
+        let _advertisement = announcement.start();
+        serve_connections(listener, crate::http::router(state), "http").await;
     })
 }
 
-pub async fn run_tftp_server(port: u16, directory: String) -> JoinHandle<()> {
+/// 逐个连接运行 axum 应用
+///
+/// `axum::serve` 在独立的任务中处理每个连接，停止服务器后保持连接的客户端仍能继续访问，
+/// 这里把连接放进 JoinSet，随接受任务一起中止。
+async fn serve_connections(listener: TcpListener, app: axum::Router, protocol: &'static str) {
+    let mut connections = JoinSet::new();
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Failed to accept {} connection: {}", protocol, e);
+                continue;
+            }
+        };
+        while connections.try_join_next().is_some() {}
+        let service = TowerToHyperService::new(app.clone().layer(Extension(ConnectInfo(peer))));
+        connections.spawn(async move {
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!(
+                    "{} connection from {} closed with error: {}",
+                    protocol, peer, e
+                );
+            }
+        });
+    }
+}
+
+pub async fn run_webdav_server(
+    username: String,
+    password: String,
+    port: u16,
+    directory: String,
+    services: Services,
+    announcement: Announcement,
+) -> JoinHandle<()> {
+    use crate::http::HttpState;
+    use crate::webdav::WebDavState;
+
+    let state = Arc::new(WebDavState::new(Arc::new(HttpState {
+        protocol: "webdav",
+        root: PathBuf::from(directory),
+        accounts: Arc::new(AccountStore::load(
+            &username,
+            &password,
+            services.guests.clone(),
+        )),
+        services,
+    })));
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("webdav server error: {:?}", e);
+                return;
+            }
+        };
+        let _advertisement = announcement.start();
+        serve_connections(listener, crate::webdav::router(state), "webdav").await;
+    })
+}
+
+pub async fn run_tftp_server(
+    port: u16,
+    directory: String,
+    services: Services,
+    announcement: Announcement,
+) -> JoinHandle<()> {
+    use crate::tftp::TftpHandler;
     use async_tftp::server::TftpServerBuilder;
+    use async_tftp::server::handlers::DirHandlerMode;
+    let mode = match services.share_mode {
+        Access::ReadWrite => DirHandlerMode::ReadWrite,
+        Access::ReadOnly => DirHandlerMode::ReadOnly,
+        Access::UploadOnly => DirHandlerMode::WriteOnly,
+    };
     tokio::spawn(async move {
-        let tftpd = TftpServerBuilder::with_dir_rw(directory)
-            .unwrap()
+        let handler = TftpHandler::new(&directory, mode, services).unwrap();
+        let tftpd = TftpServerBuilder::with_handler(handler)
+            .bind(([0, 0, 0, 0], port).into())
             .build()
             .await
             .unwrap();
+        let _advertisement = announcement.start();
         if let Err(e) = tftpd.serve().await {
             eprintln!("tftp server error: {:?}", e);
         }
@@ -1,7 +1,9 @@
+use std::path::Path;
+
 use log::{info, warn};
-use russh_sftp::protocol::{File, FileAttributes, Handle, Name, StatusCode};
-use tokio::fs;
+use russh_sftp::protocol::{File, Handle, Name, Status, StatusCode};
 
+use super::{io_status, status_ok};
 use crate::sftp::{SftpSession, utils::metadata::MetadataConverter};
 
 pub async fn opendir(
@@ -11,16 +13,17 @@ pub async fn opendir(
 ) -> Result<Handle, StatusCode> {
     info!("opendir: {}", path);
 
-    let resolved_path = session.path_resolver.resolve_path(&path)?;
-
-    match fs::read_dir(&resolved_path).await {
-        Ok(read_dir) => {
+    match session.storage.read_dir(Path::new(&path)).await {
+        Ok(entries) => {
             let handle = session.next_handle();
-            session.state.open_dirs.insert(handle.clone(), read_dir);
+            session
+                .state
+                .open_dirs
+                .insert(handle.clone(), entries.into_iter());
             Ok(Handle { id, handle })
         }
         Err(e) => {
-            warn!("Failed to open directory {:?}: {}", resolved_path, e);
+            warn!("Failed to open directory {}: {}", path, e);
             match e.kind() {
                 std::io::ErrorKind::NotFound => Err(StatusCode::NoSuchFile),
                 std::io::ErrorKind::PermissionDenied => Err(StatusCode::PermissionDenied),
@@ -40,43 +43,15 @@ pub async fn hanle_readdir(
     if let Some(read_dir) = session.state.open_dirs.get_mut(&handle) {
         let mut files = Vec::new();
 
-        // Leggi alcuni file dalla directory
-        for _ in 0..10 {
-            // Leggi massimo 10 file per volta
-            match read_dir.next_entry().await {
-                Ok(Some(entry)) => {
-                    let file_name = entry.file_name().to_string_lossy().to_string();
-                    match entry.metadata().await {
-                        Ok(metadata) => {
-                            //let attrs = Self::metadata_to_file_attributes(&metadata).await;
-                            let attrs = MetadataConverter::to_file_attributes(&metadata).await;
-                            let longname =
-                                MetadataConverter::format_longname(&file_name, &metadata).await;
-                            files.push(File {
-                                filename: file_name,
-                                longname,
-                                attrs,
-                            });
-                        }
-                        Err(e) => {
-                            warn!("Failed to get metadata for {}: {}", file_name, e);
-                            // Crea attributi di default per file regolare
-                            let mut attrs = FileAttributes::default();
-                            attrs.permissions = Some(0o100644); // File regolare con permessi rw-r--r--
-                            files.push(File {
-                                filename: file_name.clone(),
-                                longname: format!(
-                                    "-rw-r--r-- 1 root root 0 Jan  1 00:00 {}",
-                                    file_name
-                                ),
-                                attrs,
-                            });
-                        }
-                    }
-                }
-                Ok(None) => break, // Fine directory
-                Err(_) => break,
-            }
+        // Leggi massimo 10 file per volta
+        for entry in read_dir.by_ref().take(10) {
+            let attrs = MetadataConverter::to_file_attributes(&entry.metadata).await;
+            let longname = MetadataConverter::format_longname(&entry.name, &entry.metadata).await;
+            files.push(File {
+                filename: entry.name,
+                longname,
+                attrs,
+            });
         }
 
         if files.is_empty() {
@@ -88,3 +63,35 @@ pub async fn hanle_readdir(
         Err(StatusCode::BadMessage)
     }
 }
+
+pub async fn handle_mkdir(
+    session: &mut SftpSession,
+    id: u32,
+    path: String,
+) -> Result<Status, StatusCode> {
+    info!("mkdir: {}", path);
+
+    match session.storage.create_dir(Path::new(&path)).await {
+        Ok(()) => Ok(status_ok(id)),
+        Err(e) => {
+            warn!("Failed to create directory {}: {}", path, e);
+            Err(io_status(&e))
+        }
+    }
+}
+
+pub async fn handle_rmdir(
+    session: &mut SftpSession,
+    id: u32,
+    path: String,
+) -> Result<Status, StatusCode> {
+    info!("rmdir: {}", path);
+
+    match session.storage.remove_dir(Path::new(&path)).await {
+        Ok(()) => Ok(status_ok(id)),
+        Err(e) => {
+            warn!("Failed to remove directory {}: {}", path, e);
+            Err(io_status(&e))
+        }
+    }
+}
@@ -1,10 +1,17 @@
+use std::path::{Path, PathBuf};
+
 use log::{error, info, warn};
 use russh_sftp::protocol::{Data, File, Name, Status, StatusCode, Handle, OpenFlags, FileAttributes};
+use russh_sftp::server::StatusReply;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
-use tokio::fs;
 
+use super::{io_status, status_ok};
+use crate::policy::UploadCheck;
+use crate::quota::QUOTA_EXCEEDED;
 use crate::sftp::SftpSession;
 use crate::sftp::utils::file_info::FileInfo;
+use crate::throttle::Direction;
+use crate::vfs::OpenOptions;
 
 pub async fn hanlde_close(
     session: &mut SftpSession,
@@ -14,7 +21,42 @@ pub async fn hanlde_close(
     info!("close handle: {}", handle);
 
     // Rimuovi il file o directory dal tracking
-    if let Some(open_file) = session.state.open_files.remove(&handle) {
+    if let Some(mut open_file) = session.state.open_files.remove(&handle) {
+        // 上传结束时按最终大小再询问一次策略脚本，被拒绝时不保存
+        if open_file
+            .upload
+            .as_mut()
+            .is_some_and(|upload| !upload.finish())
+        {
+            warn!("Upload denied by policy for handle: {}", handle);
+            if let Err(e) = open_file.discard(session.home.as_ref()).await {
+                warn!("Failed to discard partial upload: {}", e);
+            }
+            return Ok(Status {
+                id,
+                status_code: StatusCode::PermissionDenied,
+                error_message: "Denied by policy".to_string(),
+                language_tag: "en-US".to_string(),
+            });
+        }
+        // 有些后端（例如对象存储）在关闭时才真正保存上传的内容
+        if let Err(e) = open_file.file.shutdown().await {
+            error!("Failed to close handle {}: {}", handle, e);
+            return Ok(Status {
+                id,
+                status_code: io_status(&e),
+                error_message: e.to_string(),
+                language_tag: "en-US".to_string(),
+            });
+        }
+        // 上传以关闭句柄为完成标志，记录随 `open_file` 销毁写入日志
+        if let Some(transfer) = open_file
+            .transfer
+            .as_mut()
+            .filter(|transfer| transfer.direction() == Direction::Upload)
+        {
+            transfer.set_complete();
+        }
         info!(
             "Closed file handle: {} (path: {:?}, binary: {})",
             handle, open_file.path, open_file.is_binary
@@ -53,14 +95,27 @@ pub async fn handle_read(
                     warn!("Seek to {} resulted in position {}", offset, actual_offset);
                 }
 
+                if let Some(transfer) = open_file.transfer.as_mut() {
+                    transfer.read_from(offset);
+                }
                 let mut buffer = vec![0u8; actual_len as usize];
                 match open_file.file.read(&mut buffer).await {
                     Ok(bytes_read) => {
                         if bytes_read == 0 {
                             info!("End of file reached for handle: {}", handle);
+                            if let Some(transfer) = open_file.transfer.as_mut() {
+                                transfer.set_complete();
+                            }
                             Err(StatusCode::Eof)
                         } else {
                             buffer.truncate(bytes_read);
+                            if let Some(transfer) = open_file.transfer.as_mut() {
+                                transfer.add(bytes_read as u64);
+                            }
+                            session
+                                .transfer
+                                .consume(Direction::Download, bytes_read as u64)
+                                .await;
 
                             info!(
                                 "Successfully read {} bytes from handle: {} (binary: {}, offset: {})",
@@ -83,7 +138,7 @@ pub async fn handle_read(
                     }
                     Err(e) => {
                         error!("Failed to read from file handle {}: {}", handle, e);
-                        Err(StatusCode::Failure)
+                        Err(io_status(&e))
                     }
                 }
             }
@@ -101,28 +156,16 @@ pub async fn handle_read(
 pub async fn handle_realpath(session: &mut SftpSession, id: u32, path: String) -> Result<Name, StatusCode> {
     info!("realpath: {}", path);
 
-        let resolved_path = session.path_resolver.resolve_path(&path)?;
-
-        match resolved_path.canonicalize() {
-            Ok(canonical) => {
-                // Converti il path canonico in un path relativo alla root
-                let relative_path =
-                    if let Ok(rel) = canonical.strip_prefix(&session.path_resolver.get_root_dir()) {
-                        format!("/{}", rel.to_string_lossy())
-                    } else {
-                        "/".to_string()
-                    };
-
-                Ok(Name {
-                    id,
-                    files: vec![File::dummy(&relative_path)],
-                })
-            }
-            Err(_) => Ok(Name {
-                id,
-                files: vec![File::dummy("/")],
-            }),
-        }
+    // Il path canonico è relativo alla root della sessione
+    let relative_path = match session.storage.canonicalize(Path::new(&path)).await {
+        Ok(canonical) => canonical.to_string_lossy().into_owned(),
+        Err(_) => "/".to_string(),
+    };
+
+    Ok(Name {
+        id,
+        files: vec![File::dummy(&relative_path)],
+    })
 }
 
 pub async fn handle_open(
@@ -131,111 +174,75 @@ pub async fn handle_open(
     path: String,
     pflags: OpenFlags,
     _attrs: FileAttributes,
-) -> Result<Handle, StatusCode> {
+) -> Result<Handle, StatusReply> {
     info!("open file: {} with flags: {:?}", path, pflags);
 
-    let resolved_path = session.path_resolver.resolve_path(&path)?;
-
     // Determina se è un'operazione di scrittura
     let is_write = pflags.intersects(
         OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::APPEND,
     );
 
-    if is_write {
-        // Per operazioni di scrittura, assicurati che la directory parent esista
-        if let Some(parent) = resolved_path.parent() {
-            if !parent.exists() {
-                warn!("Parent directory does not exist: {:?}", parent);
-                return Err(StatusCode::NoSuchFile);
-            }
-        }
-
-        // Crea o apri il file per scrittura
-        let open_options = {
-            let mut opts = fs::OpenOptions::new();
-
-            if pflags.contains(OpenFlags::READ) {
-                opts.read(true);
-            }
-
-            if pflags.contains(OpenFlags::WRITE) {
-                opts.write(true);
-            }
-
-            if pflags.contains(OpenFlags::CREATE) {
-                opts.create(true);
-            }
-
-            if pflags.contains(OpenFlags::TRUNCATE) {
-                opts.truncate(true);
-            }
-
-            if pflags.contains(OpenFlags::APPEND) {
-                opts.append(true);
-            }
-
-            opts
-        };
-
-        match open_options.open(&resolved_path).await {
-            Ok(file) => match FileInfo::from_file(file, resolved_path).await {
-                Ok(open_file) => {
-                    let handle = session.next_handle();
-                    info!(
-                        "Successfully opened file for write with handle: {} (binary: {})",
-                        handle, open_file.is_binary
-                    );
-                    session.state.open_files.insert(handle.clone(), open_file);
-                    Ok(Handle { id, handle })
-                }
-                Err(e) => {
-                    warn!("Failed to create FileInfo: {}", e);
-                    Err(StatusCode::Failure)
-                }
-            },
-            Err(e) => {
-                warn!("Failed to open/create file {:?}: {}", resolved_path, e);
-                match e.kind() {
-                    std::io::ErrorKind::NotFound => Err(StatusCode::NoSuchFile),
-                    std::io::ErrorKind::PermissionDenied => Err(StatusCode::PermissionDenied),
-                    std::io::ErrorKind::AlreadyExists => Err(StatusCode::Failure),
-                    _ => Err(StatusCode::Failure),
-                }
-            }
-        }
+    // 策略脚本可拒绝或改写路径
+    let policy = &session.services.policy;
+    let requested = path.clone();
+    let decision = if is_write {
+        policy.on_upload(&path, 0)
     } else {
-        // Per operazioni di lettura, il file deve esistere
-        if !resolved_path.exists() {
-            warn!("File does not exist: {:?}", resolved_path);
-            return Err(StatusCode::NoSuchFile);
-        }
-
-        // Controlla se è un file regolare
-        if !resolved_path.is_file() {
-            warn!("Path is not a regular file: {:?}", resolved_path);
-            return Err(StatusCode::Failure);
+        policy.on_download(&path)
+    };
+    let Some(path) = decision.apply(path) else {
+        warn!("Open denied by policy");
+        return Err(StatusCode::PermissionDenied.into());
+    };
+
+    // 权限和配额由存储检查
+    let path = PathBuf::from(path);
+    let options = OpenOptions {
+        read: pflags.contains(OpenFlags::READ),
+        write: pflags.contains(OpenFlags::WRITE),
+        create: pflags.contains(OpenFlags::CREATE),
+        truncate: pflags.contains(OpenFlags::TRUNCATE),
+        append: pflags.contains(OpenFlags::APPEND),
+    };
+    // 上传被拒绝时据此恢复原来的内容
+    let original = match session.storage.metadata(&path).await {
+        Ok(metadata) if !options.truncate => Some(metadata.len),
+        Ok(_) => Some(0),
+        Err(_) => None,
+    };
+    let file = session.storage.open(&path, options).await.map_err(|e| {
+        warn!("Failed to open file {:?}: {}", path, e);
+        // 截断或创建时就超出配额，与写入时一样告诉客户端原因
+        if e.kind() == std::io::ErrorKind::StorageFull {
+            StatusCode::Failure.with_message(QUOTA_EXCEEDED)
+        } else {
+            io_status(&e).into()
         }
+    })?;
 
-        match FileInfo::new(resolved_path).await {
-            Ok(open_file) => {
-                let handle = session.next_handle();
-                info!(
-                    "Successfully opened file for read with handle: {} (binary: {})",
-                    handle, open_file.is_binary
-                );
-                session.state.open_files.insert(handle.clone(), open_file);
-                Ok(Handle { id, handle })
-            }
-            Err(e) => {
-                warn!("Failed to open file: {}", e);
-                match e.kind() {
-                    std::io::ErrorKind::NotFound => Err(StatusCode::NoSuchFile),
-                    std::io::ErrorKind::PermissionDenied => Err(StatusCode::PermissionDenied),
-                    _ => Err(StatusCode::Failure),
-                }
-            }
-        }
+    let direction = if is_write {
+        Direction::Upload
+    } else {
+        Direction::Download
+    };
+    let mut open_file = FileInfo::new(file, path).await;
+    if is_write {
+        open_file.upload = Some(UploadCheck::new(
+            session.services.policy.clone(),
+            requested,
+            open_file.path.clone(),
+            0,
+        ));
     }
+    open_file.original = original;
+    open_file.transfer = Some(session.track(&open_file.path, direction));
+    let handle = session.next_handle();
+    info!(
+        "Successfully opened file with handle: {} (write: {}, binary: {})",
+        handle, is_write, open_file.is_binary
+    );
+    session.state.open_files.insert(handle.clone(), open_file);
+    Ok(Handle { id, handle })
 }
 
 pub async fn handle_write(
@@ -253,6 +260,28 @@ pub async fn handle_write(
     );
 
     if let Some(open_file) = session.state.open_files.get_mut(&handle) {
+        // 策略脚本按写入后的大小决定是否继续，文件已经打开，改写到其他路径视为拒绝
+        let size = offset + data.len() as u64;
+        let allowed = open_file
+            .upload
+            .as_mut()
+            .is_none_or(|upload| upload.wrote(size));
+        if !allowed {
+            warn!("Write denied by policy for handle: {}", handle);
+            discard_upload(session, &handle).await;
+            return Ok(Status {
+                id,
+                status_code: StatusCode::PermissionDenied,
+                error_message: "Denied by policy".to_string(),
+                language_tag: "en-US".to_string(),
+            });
+        }
+
+        session
+            .transfer
+            .consume(Direction::Upload, data.len() as u64)
+            .await;
+
         match open_file.file.seek(std::io::SeekFrom::Start(offset)).await {
             Ok(actual_offset) => {
                 if actual_offset != offset {
@@ -261,6 +290,9 @@ pub async fn handle_write(
 
                 match open_file.file.write_all(&data).await {
                     Ok(_) => {
+                        if let Some(transfer) = open_file.transfer.as_mut() {
+                            transfer.add(data.len() as u64);
+                        }
                         // Assicurati che i dati siano scritti su disco
                         if let Err(e) = open_file.file.flush().await {
                             warn!("Failed to flush file handle {}: {}", handle, e);
@@ -280,9 +312,20 @@ pub async fn handle_write(
                             language_tag: "en-US".to_string(),
                         })
                     }
+                    // 超出配额时拒绝写入
+                    Err(e) if e.kind() == std::io::ErrorKind::StorageFull => {
+                        warn!("Quota exceeded for handle: {}", handle);
+                        discard_upload(session, &handle).await;
+                        Ok(Status {
+                            id,
+                            status_code: StatusCode::Failure,
+                            error_message: QUOTA_EXCEEDED.to_string(),
+                            language_tag: "en-US".to_string(),
+                        })
+                    }
                     Err(e) => {
                         error!("Failed to write to file handle {}: {}", handle, e);
-                        Err(StatusCode::Failure)
+                        Err(io_status(&e))
                     }
                 }
             }
@@ -295,4 +338,51 @@ pub async fn handle_write(
         warn!("Invalid file handle for write: {}", handle);
         Err(StatusCode::BadMessage)
     }
-}
\ No newline at end of file
+}
+
+/// 上传被拒绝时关闭句柄并丢弃已写入的内容，与 FTP 相同，不占用刚刚检查过的配额
+async fn discard_upload(session: &mut SftpSession, handle: &str) {
+    if let Some(open_file) = session.state.open_files.remove(handle)
+        && let Err(e) = open_file.discard(session.home.as_ref()).await
+    {
+        warn!("Failed to discard partial upload: {}", e);
+    }
+}
+
+pub async fn handle_remove(
+    session: &mut SftpSession,
+    id: u32,
+    filename: String,
+) -> Result<Status, StatusCode> {
+    info!("remove: {}", filename);
+
+    match session.storage.remove_file(Path::new(&filename)).await {
+        Ok(()) => Ok(status_ok(id)),
+        Err(e) => {
+            warn!("Failed to remove {}: {}", filename, e);
+            Err(io_status(&e))
+        }
+    }
+}
+
+pub async fn handle_rename(
+    session: &mut SftpSession,
+    id: u32,
+    oldpath: String,
+    newpath: String,
+) -> Result<Status, StatusCode> {
+    info!("rename: {} -> {}", oldpath, newpath);
+
+    // SFTP v3 non sovrascrive la destinazione esistente
+    match session
+        .storage
+        .rename(Path::new(&oldpath), Path::new(&newpath))
+        .await
+    {
+        Ok(()) => Ok(status_ok(id)),
+        Err(e) => {
+            warn!("Failed to rename {} to {}: {}", oldpath, newpath, e);
+            Err(io_status(&e))
+        }
+    }
+}
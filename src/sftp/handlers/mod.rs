@@ -1,3 +1,24 @@
 pub mod dir_ops;
 pub mod file_ops;
 pub mod stat_ops;
+
+use russh_sftp::protocol::{Status, StatusCode};
+
+/// Risposta di successo per le operazioni che restituiscono solo uno stato
+pub(crate) fn status_ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+/// Converte un errore di I/O nel codice di stato SFTP corrispondente
+pub(crate) fn io_status(e: &std::io::Error) -> StatusCode {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+        std::io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        _ => StatusCode::Failure,
+    }
+}
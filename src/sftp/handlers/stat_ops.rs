@@ -1,72 +1,61 @@
+use std::path::Path;
+
 use log::{error, info, warn};
-use russh_sftp::protocol::{Attrs, StatusCode};
-use tokio::fs;
+use russh_sftp::protocol::{Attrs, FileAttributes, Status, StatusCode};
 
+use super::{io_status, status_ok};
+use crate::sftp::SftpSession;
 use crate::sftp::utils::metadata::MetadataConverter;
-use crate::sftp::SessionState;
-use crate::sftp::utils::path_resolver::PathResolver;
 
 pub async fn handle_stat(
-    _state: &SessionState,
-    path_resolver: &PathResolver,
+    session: &mut SftpSession,
     id: u32,
     path: String,
 ) -> Result<Attrs, StatusCode> {
     info!("stat: {}", path);
-    let resolved_path = path_resolver.resolve_path(&path)?;
-    match fs::metadata(&resolved_path).await {
+    match session.storage.metadata(Path::new(&path)).await {
         Ok(metadata) => {
             let attrs = MetadataConverter::to_file_attributes(&metadata).await;
             info!(
-                "stat result for {:?}: size={:?}, is_file={}",
-                resolved_path,
+                "stat result for {}: size={:?}, is_file={}",
+                path,
                 attrs.size,
                 metadata.is_file()
             );
             Ok(Attrs { id, attrs })
         }
         Err(e) => {
-            warn!("Failed to stat {:?}: {}", resolved_path, e);
-            match e.kind() {
-                std::io::ErrorKind::NotFound => Err(StatusCode::NoSuchFile),
-                std::io::ErrorKind::PermissionDenied => Err(StatusCode::PermissionDenied),
-                _ => Err(StatusCode::Failure),
-            }
+            warn!("Failed to stat {}: {}", path, e);
+            Err(io_status(&e))
         }
     }
 }
 
 pub async fn handle_lstat(
-    _state: &SessionState,
-    path_resolver: &PathResolver,
+    session: &mut SftpSession,
     id: u32,
     path: String,
 ) -> Result<Attrs, StatusCode> {
     info!("lstat: {}", path);
-    let resolved_path = path_resolver.resolve_path(&path)?;
-    match fs::symlink_metadata(&resolved_path).await {
+    match session.storage.symlink_metadata(Path::new(&path)).await {
         Ok(metadata) => {
             let attrs = MetadataConverter::to_file_attributes(&metadata).await;
             Ok(Attrs { id, attrs })
         }
         Err(e) => {
-            warn!("Failed to lstat {:?}: {}", resolved_path, e);
-            match e.kind() {
-                std::io::ErrorKind::NotFound => Err(StatusCode::NoSuchFile),
-                std::io::ErrorKind::PermissionDenied => Err(StatusCode::PermissionDenied),
-                _ => Err(StatusCode::Failure),
-            }
+            warn!("Failed to lstat {}: {}", path, e);
+            Err(io_status(&e))
         }
     }
 }
 
 pub async fn handle_fstat(
-    state: &SessionState,
+    session: &mut SftpSession,
     id: u32,
     handle: String,
 ) -> Result<Attrs, StatusCode> {
     info!("fstat handle: {}", handle);
-    if let Some(open_file) = state.open_files.get(&handle) {
+    if let Some(open_file) = session.state.open_files.get(&handle) {
         match open_file.file.metadata().await {
             Ok(metadata) => {
                 let attrs = MetadataConverter::to_file_attributes(&metadata).await;
@@ -82,3 +71,49 @@ pub async fn handle_fstat(
         Err(StatusCode::BadMessage)
     }
 }
+
+pub async fn handle_setstat(
+    session: &mut SftpSession,
+    id: u32,
+    path: String,
+    attrs: FileAttributes,
+) -> Result<Status, StatusCode> {
+    info!("setstat: {} {:?}", path, attrs);
+    apply_attrs(session, Path::new(&path), &attrs).await?;
+    Ok(status_ok(id))
+}
+
+pub async fn handle_fsetstat(
+    session: &mut SftpSession,
+    id: u32,
+    handle: String,
+    attrs: FileAttributes,
+) -> Result<Status, StatusCode> {
+    info!("fsetstat handle: {} {:?}", handle, attrs);
+    let Some(open_file) = session.state.open_files.get(&handle) else {
+        warn!("Invalid file handle for fsetstat: {}", handle);
+        return Err(StatusCode::BadMessage);
+    };
+    apply_attrs(session, &open_file.path, &attrs).await?;
+    Ok(status_ok(id))
+}
+
+/// Applica solo i permessi; gli altri attributi (tempi, proprietario) vengono ignorati
+async fn apply_attrs(
+    session: &SftpSession,
+    path: &Path,
+    attrs: &FileAttributes,
+) -> Result<(), StatusCode> {
+    let Some(mode) = attrs.permissions else {
+        return Ok(());
+    };
+
+    session
+        .storage
+        .set_permissions(path, mode)
+        .await
+        .map_err(|e| {
+            warn!("Failed to set permissions on {:?}: {}", path, e);
+            io_status(&e)
+        })
+}
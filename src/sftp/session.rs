@@ -1,34 +1,95 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, io, net::SocketAddr, path::Path, sync::Arc};
 
 use log::{error, info};
 use russh_sftp::protocol::{
     Data, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
 };
+use russh_sftp::server::StatusReply;
 
-use crate::server::ServerConfig;
+use crate::accounts::Account;
+use crate::audit::{TransferSource, TransferTracker};
+use crate::server::{ServerConfig, Services};
+use crate::throttle::{Direction, TransferThrottle};
 
-use super::{SessionState, handlers, utils::path_resolver::PathResolver};
+use crate::vfs::{self, AccountFs, LocalFs, Vfs};
+
+use super::{SessionState, handlers};
 
 pub struct SftpSession {
     pub(crate) state: SessionState,
-    pub(crate) path_resolver: PathResolver,
+    /// 文件访问都经过该存储，按账户检查权限和配额，默认为账户主目录下的本地磁盘
+    pub(crate) storage: Arc<dyn Vfs>,
+    /// 外层权限和配额检查之前的存储，只用于删除被拒绝的上传
+    pub(crate) home: Arc<dyn Vfs>,
+    pub(crate) transfer: TransferThrottle,
+    pub(crate) services: Services,
+    transfer_source: TransferSource,
 }
 
 impl SftpSession {
-    pub fn new(config: Arc<ServerConfig>) -> Self {
+    pub fn new(
+        config: Arc<ServerConfig>,
+        account: &Account,
+        peer: Option<SocketAddr>,
+    ) -> io::Result<Self> {
+        let root_dir = account.home_dir(&config.root_dir)?;
+        // 挂载点位于共享根目录下，只有能访问整个共享的账户可见
+        let storage: Arc<dyn Vfs> = if root_dir == config.root_dir {
+            vfs::share(root_dir, &config.services, &account.username)
+        } else {
+            Arc::new(LocalFs::new(root_dir))
+        };
+        Ok(Self::with_storage(config, account, peer, storage))
+    }
+
+    /// 使用指定的存储后端，例如包装后的本地磁盘或其他后端，外层会加上账户的权限和配额
+    pub fn with_storage(
+        config: Arc<ServerConfig>,
+        account: &Account,
+        peer: Option<SocketAddr>,
+        storage: Arc<dyn Vfs>,
+    ) -> Self {
+        let transfer_source = TransferSource {
+            protocol: "sftp",
+            username: account.username.clone(),
+            anonymous: account.anonymous,
+            remote_host: peer.map_or_else(|| "-".to_string(), |peer| peer.ip().to_string()),
+        };
+        let home = storage.clone();
+        let storage = AccountFs::new(
+            storage,
+            &config.services,
+            transfer_source.clone(),
+            account.access,
+            account.permissions.clone(),
+        )
+        .with_quota(account.quota());
         Self {
             state: SessionState {
                 version: None,
-                _root_dir: config.root_dir.clone(),
                 open_files: HashMap::new(),
                 open_dirs: HashMap::new(),
                 handle_counter: 0,
                 max_read_size: config.max_read_size,
             },
-            path_resolver: PathResolver::new(config.root_dir.clone()),
+            storage: Arc::new(storage),
+            home,
+            transfer: config.services.throttle.session(&account.username),
+            services: config.services.clone(),
+            transfer_source,
         }
     }
 
+    /// 为打开的文件创建传输记录，关闭句柄时写入日志
+    pub fn track(&self, path: &Path, direction: Direction) -> TransferTracker {
+        TransferTracker::new(
+            &self.services,
+            self.transfer_source.clone(),
+            self.storage.display_path(path),
+            direction,
+        )
+    }
+
     pub fn next_handle(&mut self) -> String {
         self.state.handle_counter += 1;
         format!("handle_{}", self.state.handle_counter)
@@ -36,10 +97,11 @@ impl SftpSession {
 }
 
 impl russh_sftp::server::Handler for SftpSession {
-    type Error = StatusCode;
+    /// 处理函数大多只返回状态码，需要说明原因时附带消息，例如超出配额
+    type Error = StatusReply;
 
     fn unimplemented(&self) -> Self::Error {
-        StatusCode::OpUnsupported
+        StatusCode::OpUnsupported.into()
     }
 
     async fn init(
@@ -49,7 +111,7 @@ impl russh_sftp::server::Handler for SftpSession {
     ) -> Result<Version, Self::Error> {
         if self.state.version.is_some() {
             error!("duplicate SSH_FXP_VERSION packet");
-            return Err(StatusCode::ConnectionLost);
+            return Err(StatusCode::ConnectionLost.into());
         }
 
         self.state.version = Some(version);
@@ -71,7 +133,9 @@ impl russh_sftp::server::Handler for SftpSession {
     }
 
     async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
-        handlers::file_ops::hanlde_close(self, id, handle).await
+        handlers::file_ops::hanlde_close(self, id, handle)
+            .await
+            .map_err(Into::into)
     }
 
     /// Implementazione migliorata del comando READ per supportare download di file di testo e binari
@@ -82,7 +146,9 @@ impl russh_sftp::server::Handler for SftpSession {
         offset: u64,
         len: u32,
     ) -> Result<Data, Self::Error> {
-        handlers::file_ops::handle_read(self, id, handle, offset, len).await
+        handlers::file_ops::handle_read(self, id, handle, offset, len)
+            .await
+            .map_err(Into::into)
     }
 
     /// Implementazione del comando WRITE per supportare upload di file
@@ -93,7 +159,9 @@ impl russh_sftp::server::Handler for SftpSession {
         offset: u64,
         data: Vec<u8>,
     ) -> Result<Status, Self::Error> {
-        handlers::file_ops::handle_write(self, id, handle, offset, data).await
+        handlers::file_ops::handle_write(self, id, handle, offset, data)
+            .await
+            .map_err(Into::into)
     }
 
     async fn lstat(
@@ -101,7 +169,9 @@ impl russh_sftp::server::Handler for SftpSession {
         id: u32,
         path: String,
     ) -> Result<russh_sftp::protocol::Attrs, Self::Error> {
-        handlers::stat_ops::handle_lstat(&self.state, &self.path_resolver, id, path).await
+        handlers::stat_ops::handle_lstat(self, id, path)
+            .await
+            .map_err(Into::into)
     }
 
     async fn fstat(
@@ -109,19 +179,83 @@ impl russh_sftp::server::Handler for SftpSession {
         id: u32,
         handle: String,
     ) -> Result<russh_sftp::protocol::Attrs, Self::Error> {
-        handlers::stat_ops::handle_fstat(&self.state, id, handle).await
+        handlers::stat_ops::handle_fstat(self, id, handle)
+            .await
+            .map_err(Into::into)
     }
 
     async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
-        handlers::dir_ops::opendir(self, id, path).await
+        handlers::dir_ops::opendir(self, id, path)
+            .await
+            .map_err(Into::into)
     }
 
     async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
-        handlers::dir_ops::hanle_readdir(self, id, handle).await
+        handlers::dir_ops::hanle_readdir(self, id, handle)
+            .await
+            .map_err(Into::into)
     }
 
     async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
-        handlers::file_ops::handle_realpath(self, id, path).await
+        handlers::file_ops::handle_realpath(self, id, path)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        handlers::file_ops::handle_remove(self, id, filename)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, Self::Error> {
+        handlers::file_ops::handle_rename(self, id, oldpath, newpath)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        handlers::dir_ops::handle_mkdir(self, id, path)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        handlers::dir_ops::handle_rmdir(self, id, path)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn setstat(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        handlers::stat_ops::handle_setstat(self, id, path, attrs)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        handle: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        handlers::stat_ops::handle_fsetstat(self, id, handle, attrs)
+            .await
+            .map_err(Into::into)
     }
 
     async fn stat(
@@ -129,6 +263,8 @@ impl russh_sftp::server::Handler for SftpSession {
         id: u32,
         path: String,
     ) -> Result<russh_sftp::protocol::Attrs, Self::Error> {
-        handlers::stat_ops::handle_stat(&self.state, &self.path_resolver, id, path).await
+        handlers::stat_ops::handle_stat(self, id, path)
+            .await
+            .map_err(Into::into)
     }
 }
@@ -1,16 +1,15 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
-use tokio::fs;
+use std::vec;
 
 use super::utils::file_info::FileInfo;
+use crate::vfs::DirEntry;
 
 pub type HandleId = String;
 pub type OpenFiles = HashMap<HandleId, FileInfo>;
-pub type OpenDirs = HashMap<HandleId, fs::ReadDir>;
+pub type OpenDirs = HashMap<HandleId, vec::IntoIter<DirEntry>>;
 
 pub struct SessionState {
     pub version: Option<u32>,
-    pub _root_dir: PathBuf,
     pub open_files: OpenFiles,
     pub open_dirs: OpenDirs,
     pub handle_counter: u32,
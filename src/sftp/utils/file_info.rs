@@ -1,41 +1,56 @@
+use std::io;
 use std::path::{Path, PathBuf};
 
 use log::info;
-use tokio::{fs, io::AsyncReadExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::audit::TransferTracker;
+use crate::policy::UploadCheck;
+use crate::vfs::{Vfs, VfsFile};
 
 #[derive(Debug)]
 pub struct FileInfo {
-    pub file: fs::File,
+    pub file: Box<dyn VfsFile>,
+    /// 客户端请求的路径（策略改写后），用于检查权限
     pub path: PathBuf,
+    /// 写入句柄上传过程中再次询问策略脚本
+    pub upload: Option<UploadCheck>,
     pub is_binary: bool,
+    /// 传输审计记录，随句柄关闭一起写入日志
+    pub transfer: Option<TransferTracker>,
+    /// 写入句柄打开前文件的长度，由该句柄新建的文件为 `None`
+    pub original: Option<u64>,
 }
 
 impl FileInfo {
-    pub async fn new(path: PathBuf) -> Result<Self, std::io::Error> {
-        let file = fs::File::open(&path).await?;
-        let is_binary = Self::detect_binary_file(&path).await;
+    pub async fn new(mut file: Box<dyn VfsFile>, path: PathBuf) -> Self {
+        let is_binary = Self::detect_binary_file(file.as_mut(), &path).await;
 
         info!("Opened file: {:?}, binary: {}", path, is_binary);
 
-        Ok(Self {
+        Self {
             file,
+            upload: None,
             path,
             is_binary,
-        })
+            transfer: None,
+            original: None,
+        }
     }
 
-    // Nuovo metodo per creare da un file già aperto
-    pub async fn from_file(file: fs::File, path: PathBuf) -> Result<Self, std::io::Error> {
-        let is_binary = Self::detect_binary_file(&path).await;
-
-        Ok(Self {
-            file,
-            path,
-            is_binary,
-        })
+    /// 丢弃被拒绝的上传：已有文件截回打开前的长度（覆盖上传时为空），新建的文件删除。
+    /// `fs` 为不检查权限的存储，没有删除权限的账户也能撤销自己新建的文件。
+    pub async fn discard(mut self, fs: &dyn Vfs) -> io::Result<()> {
+        match self.original {
+            Some(len) => self.file.set_len(len).await,
+            None => {
+                drop(self.file);
+                fs.remove_file(&self.path).await
+            }
+        }
     }
 
-    async fn detect_binary_file(path: &Path) -> bool {
+    async fn detect_binary_file(file: &mut dyn VfsFile, path: &Path) -> bool {
         // Controllo basato sull'estensione del file
         if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
             let binary_extensions = [
@@ -51,28 +66,77 @@ impl FileInfo {
             }
         }
 
-        // Controllo del contenuto del file (primi 512 bytes)
-        if let Ok(mut file) = fs::File::open(path).await {
-            let mut buffer = [0u8; 512];
-            if let Ok(bytes_read) = file.read(&mut buffer).await {
-                let sample = &buffer[..bytes_read];
-
-                // Se contiene byte nulli, probabilmente è binario
-                if sample.contains(&0) {
-                    return true;
-                }
-
-                // Controlla la percentuale di caratteri non-ASCII
-                let non_ascii_count = sample.iter().filter(|&&b| b > 127).count();
-                let non_ascii_percentage = (non_ascii_count as f32 / bytes_read as f32) * 100.0;
-
-                // Se più del 30% dei caratteri sono non-ASCII, probabilmente è binario
-                if non_ascii_percentage > 30.0 {
-                    return true;
-                }
+        // Controllo del contenuto del file (primi 512 bytes), poi torna all'inizio
+        let mut buffer = [0u8; 512];
+        let bytes_read = file.read(&mut buffer).await;
+        let _ = file.rewind().await;
+        if let Ok(bytes_read) = bytes_read {
+            let sample = &buffer[..bytes_read];
+
+            // Se contiene byte nulli, probabilmente è binario
+            if sample.contains(&0) {
+                return true;
+            }
+
+            // Controlla la percentuale di caratteri non-ASCII
+            let non_ascii_count = sample.iter().filter(|&&b| b > 127).count();
+            let non_ascii_percentage = (non_ascii_count as f32 / bytes_read as f32) * 100.0;
+
+            // Se più del 30% dei caratteri sono non-ASCII, probabilmente è binario
+            if non_ascii_percentage > 30.0 {
+                return true;
             }
         }
 
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::vfs::{MemoryFs, OpenOptions};
+
+    async fn upload(fs: &MemoryFs, path: &str, original: Option<u64>, data: &[u8]) -> FileInfo {
+        let options = OpenOptions {
+            write: true,
+            create: true,
+            ..Default::default()
+        };
+        let mut file = fs.open(Path::new(path), options).await.unwrap();
+        file.seek(io::SeekFrom::End(0)).await.unwrap();
+        file.write_all(data).await.unwrap();
+        let mut info = FileInfo::new(file, PathBuf::from(path)).await;
+        info.original = original;
+        info
+    }
+
+    async fn content(fs: &MemoryFs, path: &str) -> io::Result<Vec<u8>> {
+        let mut file = fs.open(Path::new(path), OpenOptions::read()).await?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn discard_restores_existing_file() {
+        let fs = MemoryFs::new(None);
+        upload(&fs, "/a.txt", None, b"hello").await;
+        let info = upload(&fs, "/a.txt", Some(5), b" world").await;
+        assert_eq!(content(&fs, "/a.txt").await.unwrap(), b"hello world");
+        info.discard(&fs).await.unwrap();
+        assert_eq!(content(&fs, "/a.txt").await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn discard_removes_created_file() {
+        let fs = MemoryFs::new(None);
+        let info = upload(&fs, "/new.txt", None, b"partial").await;
+        info.discard(&fs).await.unwrap();
+        let e = content(&fs, "/new.txt").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert_eq!(fs.usage().await.unwrap().bytes, 0);
+    }
+}
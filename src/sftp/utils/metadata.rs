@@ -1,114 +1,81 @@
 use russh_sftp::protocol::FileAttributes;
 
+use crate::vfs::{FileKind, Metadata};
+
 pub struct MetadataConverter;
 
 impl MetadataConverter {
-    pub async fn to_file_attributes(metadata: &std::fs::Metadata) -> FileAttributes {
+    pub async fn to_file_attributes(metadata: &Metadata) -> FileAttributes {
         let mut attrs = FileAttributes::default();
-        attrs.size = Some(metadata.len());
-
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::MetadataExt;
-            attrs.uid = Some(metadata.uid());
-            attrs.gid = Some(metadata.gid());
-
-            let mut mode = metadata.mode();
-            if metadata.is_file() {
-                mode |= 0o100000; // S_IFREG
-            } else if metadata.is_dir() {
-                mode |= 0o040000; // S_IFDIR
-            } else if metadata.file_type().is_symlink() {
-                mode |= 0o120000; // S_IFLNK
-            }
-            attrs.permissions = Some(mode);
-        }
-
-        #[cfg(windows)]
-        {
-            let mut mode = 0o644;
-            if metadata.is_dir() {
-                mode = 0o755 | 0o040000;
-            } else if metadata.is_file() {
-                mode = 0o644 | 0o100000;
-            }
-            attrs.permissions = Some(mode);
-        }
-
-        if let Ok(modified) = metadata.modified() {
-            if let Ok(duration) = modified.duration_since(std::time::UNIX_EPOCH) {
-                attrs.mtime = Some(duration.as_secs() as u32);
-            }
-        }
+        attrs.size = Some(metadata.len);
+        attrs.uid = metadata.uid;
+        attrs.gid = metadata.gid;
+        attrs.permissions = Some(Self::mode(metadata) | Self::type_bits(metadata.kind));
 
-        if let Ok(accessed) = metadata.accessed() {
-            if let Ok(duration) = accessed.duration_since(std::time::UNIX_EPOCH) {
-                attrs.atime = Some(duration.as_secs() as u32);
-            }
-        }
+        attrs.mtime = metadata.modified.and_then(Self::unix_time);
+        attrs.atime = metadata.accessed.and_then(Self::unix_time);
 
         attrs
     }
 
-    pub async fn format_longname(filename: &str, metadata: &std::fs::Metadata) -> String {
-        let file_type = if metadata.is_dir() {
-            'd'
-        } else if metadata.file_type().is_symlink() {
-            'l'
-        } else {
-            '-'
+    pub async fn format_longname(filename: &str, metadata: &Metadata) -> String {
+        let file_type = match metadata.kind {
+            FileKind::Dir => 'd',
+            FileKind::Symlink => 'l',
+            FileKind::File => '-',
         };
 
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::MetadataExt;
-            let mode = metadata.mode();
-            let permissions = format!(
-                "{}{}{}{}{}{}{}{}{}{}",
-                file_type,
-                if mode & 0o400 != 0 { 'r' } else { '-' },
-                if mode & 0o200 != 0 { 'w' } else { '-' },
-                if mode & 0o100 != 0 { 'x' } else { '-' },
-                if mode & 0o040 != 0 { 'r' } else { '-' },
-                if mode & 0o020 != 0 { 'w' } else { '-' },
-                if mode & 0o010 != 0 { 'x' } else { '-' },
-                if mode & 0o004 != 0 { 'r' } else { '-' },
-                if mode & 0o002 != 0 { 'w' } else { '-' },
-                if mode & 0o001 != 0 { 'x' } else { '-' },
-            );
+        let mode = Self::mode(metadata);
+        let permissions = format!(
+            "{}{}{}{}{}{}{}{}{}{}",
+            file_type,
+            if mode & 0o400 != 0 { 'r' } else { '-' },
+            if mode & 0o200 != 0 { 'w' } else { '-' },
+            if mode & 0o100 != 0 { 'x' } else { '-' },
+            if mode & 0o040 != 0 { 'r' } else { '-' },
+            if mode & 0o020 != 0 { 'w' } else { '-' },
+            if mode & 0o010 != 0 { 'x' } else { '-' },
+            if mode & 0o004 != 0 { 'r' } else { '-' },
+            if mode & 0o002 != 0 { 'w' } else { '-' },
+            if mode & 0o001 != 0 { 'x' } else { '-' },
+        );
 
-            let nlink = metadata.nlink();
-            let uid = metadata.uid();
-            let gid = metadata.gid();
-            let size = metadata.len();
+        // Senza informazioni sul proprietario (es. Windows) usa root
+        let owner = |id: Option<u32>| id.map_or_else(|| "root".to_string(), |id| id.to_string());
 
-            // Formato data semplificato
-            let mtime = if let Ok(_modified) = metadata.modified() {
-                // Simplified date format - for full formatting, chrono could be added
-                "Jan  1 00:00".to_string()
-            } else {
-                "Jan  1 00:00".to_string()
-            };
+        // Formato data semplificato
+        let mtime = "Jan  1 00:00";
 
-            format!(
-                "{} {:3} {:5} {:5} {:8} {} {}",
-                permissions, nlink, uid, gid, size, mtime, filename
-            )
-        }
+        format!(
+            "{} {:3} {:5} {:5} {:8} {} {}",
+            permissions,
+            metadata.nlink.unwrap_or(1),
+            owner(metadata.uid),
+            owner(metadata.gid),
+            metadata.len,
+            mtime,
+            filename
+        )
+    }
 
-        #[cfg(windows)]
-        {
-            let permissions = if metadata.is_dir() {
-                "drwxr-xr-x"
-            } else {
-                "-rw-r--r--"
-            };
+    /// Permessi del file; se il backend non li fornisce usa valori predefiniti
+    fn mode(metadata: &Metadata) -> u32 {
+        metadata
+            .mode
+            .unwrap_or(if metadata.is_dir() { 0o755 } else { 0o644 })
+    }
+
+    fn unix_time(time: std::time::SystemTime) -> Option<u32> {
+        time.duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs() as u32)
+    }
 
-            let size = metadata.len();
-            format!(
-                "{} 1 root root {:8} Jan  1 00:00 {}",
-                permissions, size, filename
-            )
+    fn type_bits(kind: FileKind) -> u32 {
+        match kind {
+            FileKind::File => 0o100000,    // S_IFREG
+            FileKind::Dir => 0o040000,     // S_IFDIR
+            FileKind::Symlink => 0o120000, // S_IFLNK
         }
     }
 }
@@ -2,6 +2,7 @@ use log::warn;
 use russh_sftp::protocol::StatusCode;
 use std::path::{Path, PathBuf};
 
+#[derive(Debug)]
 pub struct PathResolver {
     root_dir: PathBuf,
 }
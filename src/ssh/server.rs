@@ -1,5 +1,7 @@
 use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
+use crate::accounts::AccountStore;
+use crate::server::Services;
 use crate::ssh::session::SshSession;
 
 #[derive(Clone)]
@@ -9,16 +11,16 @@ pub struct Server {
 
 #[derive(Debug)]
 pub struct ServerConfig {
-    pub username: String,
-    pub password: String,
+    pub accounts: Arc<AccountStore>,
     pub root_dir: PathBuf,
     pub max_read_size: u32,
+    pub services: Services,
 }
 
 impl russh::server::Server for Server {
     type Handler = SshSession;
 
-    fn new_client(&mut self, _: Option<SocketAddr>) -> Self::Handler {
-        SshSession::new(self.config.clone())
+    fn new_client(&mut self, peer: Option<SocketAddr>) -> Self::Handler {
+        SshSession::new(self.config.clone(), peer)
     }
 }
@@ -1,28 +1,111 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
-use log::info;
+use log::{info, warn};
 use russh::server::{Auth, Msg, Session};
 use russh::{Channel, ChannelId};
 
 use tokio::sync::Mutex;
 
+use crate::accounts::Account;
+use crate::audit::TransferSource;
+use crate::hooks::{Event, EventKind};
+use crate::limits::UserGuard;
 use crate::server::ServerConfig;
 use crate::sftp::SftpSession;
 
 pub struct SshSession {
     clients: Arc<Mutex<HashMap<ChannelId, Channel<Msg>>>>,
     config: Arc<ServerConfig>,
+    account: Option<Account>,
+    peer: Option<SocketAddr>,
+    /// 登录后占用的单用户连接数
+    user_guard: Option<UserGuard>,
 }
 
 impl SshSession {
-    pub fn new(config: Arc<ServerConfig>) -> Self {
+    pub fn new(config: Arc<ServerConfig>, peer: Option<SocketAddr>) -> Self {
         Self {
             clients: Arc::new(Mutex::new(HashMap::new())),
             config,
+            account: None,
+            peer,
+            user_guard: None,
         }
     }
 
+    /// 账户校验通过后再经账户 IP 规则、策略脚本和单用户连接数放行，记录账户并触发登录钩子
+    fn login(&mut self, user: &str, account: Option<Account>) -> Auth {
+        let remote_host = self
+            .peer
+            .map_or_else(|| "-".to_string(), |peer| peer.ip().to_string());
+        let account = account
+            .filter(|account| self.peer.is_none_or(|peer| account.permits_ip(peer.ip())))
+            .filter(|_| {
+                self.config
+                    .services
+                    .policy
+                    .authorize(user, &remote_host, "sftp")
+            });
+        let user_guard = account
+            .as_ref()
+            .and_then(|account| self.config.services.connections.login(&account.username));
+        let account = account.filter(|_| user_guard.is_some());
+        let accepted = account.is_some();
+        let source = TransferSource {
+            protocol: "sftp",
+            username: user.to_string(),
+            anonymous: account.as_ref().is_some_and(|account| account.anonymous),
+            remote_host,
+        };
+        self.account = account;
+        self.user_guard = user_guard;
+
+        if accepted {
+            self.config
+                .services
+                .hooks
+                .fire(Event::new(EventKind::Login, source));
+            Auth::Accept
+        } else {
+            self.config
+                .services
+                .hooks
+                .fire(Event::new(EventKind::LoginFailed, source));
+            Auth::Reject {
+                proceed_with_methods: None,
+                partial_success: false,
+            }
+        }
+    }
+
+    /// 记录密码或公钥校验结果，用于锁定反复猜测密码的 IP 和用户名
+    fn record_attempt(&self, user: &str, success: bool) {
+        let Some(peer) = self.peer else {
+            return;
+        };
+        let lockouts = &self.config.services.lockouts;
+        if success {
+            lockouts.record_success(user);
+        } else {
+            lockouts.record_failure(peer.ip(), user);
+        }
+    }
+
+    fn is_locked(&self, user: &str) -> bool {
+        let locked = self
+            .peer
+            .is_some_and(|peer| self.config.services.lockouts.is_locked(peer.ip(), user));
+        if locked {
+            warn!(
+                "Rejected SSH login of {} from {:?}: locked out",
+                user, self.peer
+            );
+        }
+        locked
+    }
+
     pub async fn get_channel(&mut self, channel_id: ChannelId) -> Channel<Msg> {
         let mut clients = self.clients.lock().await;
         clients.remove(&channel_id).unwrap()
@@ -33,17 +116,13 @@ impl russh::server::Handler for SshSession {
     type Error = anyhow::Error;
 
     async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
-        info!("credentials: {}, {}", user, password);
-        if self.config.username.len() == 0 && self.config.password.len() == 0 {
-            Ok(Auth::Accept)
-        } else if user == self.config.username && password == self.config.password {
-            Ok(Auth::Accept)
-        } else {
-            Ok(Auth::Reject {
-                proceed_with_methods: None,
-                partial_success: false,
-            })
+        info!("Password login attempt for {}", user);
+        if self.is_locked(user) {
+            return Ok(self.login(user, None));
         }
+        let account = self.config.accounts.authenticate(user, password);
+        self.record_attempt(user, account.is_some());
+        Ok(self.login(user, account))
     }
 
     async fn auth_publickey(
@@ -51,8 +130,14 @@ impl russh::server::Handler for SshSession {
         user: &str,
         public_key: &russh::keys::PublicKey,
     ) -> Result<Auth, Self::Error> {
-        info!("credentials: {}, {:?}", user, public_key);
-        Ok(Auth::Accept)
+        info!("Public key login attempt for {}: {:?}", user, public_key);
+        if self.is_locked(user) {
+            return Ok(self.login(user, None));
+        }
+        // 没有为账户配置公钥，只有匿名模式下接受任意公钥
+        let account = self.config.accounts.login_without_password(user);
+        self.record_attempt(user, account.is_some());
+        Ok(self.login(user, account))
     }
 
     async fn channel_eof(
@@ -85,8 +170,19 @@ impl russh::server::Handler for SshSession {
         info!("subsystem: {}", name);
 
         if name == "sftp" {
+            let account = self.account.clone().unwrap_or_default();
+            let sftp = match SftpSession::new(self.config.clone(), &account, self.peer) {
+                Ok(sftp) => sftp,
+                Err(e) => {
+                    warn!(
+                        "Failed to start SFTP session for {}: {}",
+                        account.username, e
+                    );
+                    session.channel_failure(channel_id)?;
+                    return Ok(());
+                }
+            };
             let channel = self.get_channel(channel_id).await;
-            let sftp = SftpSession::new(self.config.clone());
             session.channel_success(channel_id)?;
             russh_sftp::server::run(channel.into_stream(), sftp).await;
         } else {
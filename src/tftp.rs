@@ -0,0 +1,83 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use async_tftp::packet;
+use async_tftp::server::Handler;
+use async_tftp::server::handlers::{DirHandler, DirHandlerMode};
+
+use crate::audit::{Audited, TransferSource, TransferTracker};
+use crate::server::Services;
+use crate::throttle::{Direction, Throttled};
+
+/// 包装 `DirHandler`，为每次 TFTP 传输施加限速并记录传输日志
+pub struct TftpHandler {
+    inner: DirHandler,
+    directory: PathBuf,
+    services: Services,
+}
+
+impl TftpHandler {
+    pub fn new(
+        directory: &str,
+        mode: DirHandlerMode,
+        services: Services,
+    ) -> async_tftp::Result<Self> {
+        Ok(Self {
+            inner: DirHandler::new(directory, mode)?,
+            directory: PathBuf::from(directory),
+            services,
+        })
+    }
+
+    /// TFTP 没有登录，统一记为匿名用户
+    fn track(&self, client: &SocketAddr, path: &Path, direction: Direction) -> TransferTracker {
+        TransferTracker::new(
+            &self.services,
+            TransferSource {
+                protocol: "tftp",
+                username: "anonymous".to_string(),
+                anonymous: true,
+                remote_host: client.ip().to_string(),
+            },
+            self.directory
+                .join(path.strip_prefix("/").unwrap_or(path))
+                .to_string_lossy()
+                .into_owned(),
+            direction,
+        )
+    }
+}
+
+impl Handler for TftpHandler {
+    type Reader = Throttled<Audited<<DirHandler as Handler>::Reader>>;
+    type Writer = Throttled<Audited<<DirHandler as Handler>::Writer>>;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        if !self.services.accepts("tftp", *client) {
+            return Err(packet::Error::PermissionDenied);
+        }
+        let (reader, size) = self.inner.read_req_open(client, path).await?;
+        let reader = Audited::new(reader, self.track(client, path, Direction::Download));
+        let transfer = self.services.throttle.session("");
+        Ok((Throttled::new(reader, transfer, Direction::Download), size))
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        if !self.services.accepts("tftp", *client) {
+            return Err(packet::Error::PermissionDenied);
+        }
+        let writer = self.inner.write_req_open(client, path, size).await?;
+        let writer = Audited::new(writer, self.track(client, path, Direction::Upload));
+        let transfer = self.services.throttle.session("");
+        Ok(Throttled::new(writer, transfer, Direction::Upload))
+    }
+}
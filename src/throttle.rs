@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, Instant};
+
+use tokio::time::Sleep;
+
+/// 限速配置，单位为字节/秒，0 表示不限速
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BandwidthLimits {
+    pub upload: u64,
+    pub download: u64,
+    pub session: u64,
+    pub user: u64,
+}
+
+/// 令牌桶限速器，桶容量为一秒的流量
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: Arc<AtomicU64>,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: Arc<AtomicU64>) -> Self {
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    /// 预留 `bytes` 个令牌，返回需要等待的时长
+    pub fn reserve(&self, bytes: u64) -> Duration {
+        let rate = self.rate.load(Ordering::Relaxed);
+        if rate == 0 {
+            return Duration::ZERO;
+        }
+
+        let rate = rate as f64;
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.last = now;
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.tokens -= bytes as f64;
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// 全局限速状态，所有协议共用，修改后对正在进行的传输立即生效
+#[derive(Debug)]
+pub struct Throttle {
+    upload: RateLimiter,
+    download: RateLimiter,
+    session_rate: Arc<AtomicU64>,
+    user_rate: Arc<AtomicU64>,
+    users: Mutex<HashMap<String, Arc<RateLimiter>>>,
+}
+
+impl Throttle {
+    pub fn new(limits: BandwidthLimits) -> Self {
+        Self {
+            upload: RateLimiter::new(Arc::new(AtomicU64::new(limits.upload))),
+            download: RateLimiter::new(Arc::new(AtomicU64::new(limits.download))),
+            session_rate: Arc::new(AtomicU64::new(limits.session)),
+            user_rate: Arc::new(AtomicU64::new(limits.user)),
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_limits(&self, limits: BandwidthLimits) {
+        self.upload.rate.store(limits.upload, Ordering::Relaxed);
+        self.download.rate.store(limits.download, Ordering::Relaxed);
+        self.session_rate.store(limits.session, Ordering::Relaxed);
+        self.user_rate.store(limits.user, Ordering::Relaxed);
+    }
+
+    /// 为新会话创建限速句柄，`username` 为空时不启用单用户限速
+    pub fn session(self: &Arc<Self>, username: &str) -> TransferThrottle {
+        let mut transfer = TransferThrottle {
+            throttle: self.clone(),
+            session: Arc::new(RateLimiter::new(self.session_rate.clone())),
+            user: None,
+        };
+        transfer.bind_user(username);
+        transfer
+    }
+
+    fn user_limiter(&self, username: &str) -> Arc<RateLimiter> {
+        let mut users = self.users.lock().unwrap();
+        users
+            .entry(username.to_string())
+            .or_insert_with(|| Arc::new(RateLimiter::new(self.user_rate.clone())))
+            .clone()
+    }
+}
+
+/// 单个会话的限速句柄，同时受全局、会话和用户三级限速约束
+#[derive(Debug, Clone)]
+pub struct TransferThrottle {
+    throttle: Arc<Throttle>,
+    session: Arc<RateLimiter>,
+    user: Option<Arc<RateLimiter>>,
+}
+
+impl TransferThrottle {
+    /// 登录成功后绑定用户，使该用户的所有会话共享同一个限速器
+    pub fn bind_user(&mut self, username: &str) {
+        self.user = if username.is_empty() {
+            None
+        } else {
+            Some(self.throttle.user_limiter(username))
+        };
+    }
+
+    pub fn delay(&self, direction: Direction, bytes: u64) -> Duration {
+        let global = match direction {
+            Direction::Upload => self.throttle.upload.reserve(bytes),
+            Direction::Download => self.throttle.download.reserve(bytes),
+        };
+        let session = self.session.reserve(bytes);
+        let user = self
+            .user
+            .as_ref()
+            .map(|user| user.reserve(bytes))
+            .unwrap_or(Duration::ZERO);
+        global.max(session).max(user)
+    }
+
+    pub async fn consume(&self, direction: Direction, bytes: u64) {
+        let delay = self.delay(direction, bytes);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// 为读写流加上限速，同时支持 tokio 与 futures 的 IO trait
+pub struct Throttled<T> {
+    inner: T,
+    transfer: TransferThrottle,
+    direction: Direction,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, transfer: TransferThrottle, direction: Direction) -> Self {
+        Self {
+            inner,
+            transfer,
+            direction,
+            delay: None,
+        }
+    }
+
+    fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(delay) = self.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        Poll::Ready(())
+    }
+
+    fn charge(&mut self, bytes: usize) {
+        let delay = self.transfer.delay(self.direction, bytes as u64);
+        if !delay.is_zero() {
+            self.delay = Some(Box::pin(tokio::time::sleep(delay)));
+        }
+    }
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Throttled<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_delay(cx));
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.charge(buf.filled().len() - before);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: futures_lite::AsyncRead + Unpin> futures_lite::AsyncRead for Throttled<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_delay(cx));
+        let n = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.charge(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: futures_lite::AsyncWrite + Unpin> futures_lite::AsyncWrite for Throttled<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_delay(cx));
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.charge(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(rate: u64) -> RateLimiter {
+        RateLimiter::new(Arc::new(AtomicU64::new(rate)))
+    }
+
+    /// 把上次补充令牌的时间往前拨，模拟经过了 `elapsed`
+    fn elapse(limiter: &RateLimiter, elapsed: Duration) {
+        let mut bucket = limiter.bucket.lock().unwrap();
+        bucket.last = bucket.last.checked_sub(elapsed).unwrap();
+    }
+
+    fn approx(actual: Duration, expected: f64) -> bool {
+        (actual.as_secs_f64() - expected).abs() < 0.05
+    }
+
+    #[test]
+    fn zero_rate_is_unlimited() {
+        let limiter = limiter(0);
+        assert_eq!(limiter.reserve(u64::MAX), Duration::ZERO);
+        assert_eq!(limiter.reserve(u64::MAX), Duration::ZERO);
+    }
+
+    #[test]
+    fn starts_empty_and_waits_for_tokens() {
+        let limiter = limiter(1000);
+        assert!(approx(limiter.reserve(500), 0.5));
+        // 欠下的令牌由后续预留承担
+        assert!(approx(limiter.reserve(500), 1.0));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = limiter(1000);
+        elapse(&limiter, Duration::from_millis(500));
+        assert_eq!(limiter.reserve(400), Duration::ZERO);
+        assert!(approx(limiter.reserve(200), 0.1));
+    }
+
+    #[test]
+    fn burst_is_capped_at_one_second() {
+        let limiter = limiter(1000);
+        elapse(&limiter, Duration::from_secs(10));
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        assert!(approx(limiter.reserve(1000), 1.0));
+    }
+
+    #[test]
+    fn rate_changes_apply_to_existing_sessions() {
+        let throttle = Arc::new(Throttle::new(BandwidthLimits::default()));
+        let transfer = throttle.session("alice");
+        assert_eq!(transfer.delay(Direction::Upload, 1 << 30), Duration::ZERO);
+
+        throttle.set_limits(BandwidthLimits {
+            download: 1000,
+            ..Default::default()
+        });
+        assert_eq!(transfer.delay(Direction::Upload, 1000), Duration::ZERO);
+        assert!(approx(transfer.delay(Direction::Download, 1000), 1.0));
+
+        throttle.set_limits(BandwidthLimits::default());
+        assert_eq!(transfer.delay(Direction::Download, 1000), Duration::ZERO);
+    }
+
+    #[test]
+    fn slowest_level_wins() {
+        let throttle = Arc::new(Throttle::new(BandwidthLimits {
+            upload: 4000,
+            session: 2000,
+            user: 1000,
+            ..Default::default()
+        }));
+        let first = throttle.session("alice");
+        let second = throttle.session("alice");
+        assert!(approx(first.delay(Direction::Upload, 500), 0.5));
+        // 同一用户的会话共享用户限速器
+        assert!(approx(second.delay(Direction::Upload, 500), 1.0));
+        // 匿名会话不受单用户限速
+        let anonymous = throttle.session("");
+        assert!(approx(anonymous.delay(Direction::Upload, 1000), 0.5));
+    }
+}
@@ -0,0 +1,336 @@
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use async_trait::async_trait;
+use log::warn;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use super::{DirEntry, Metadata, OpenOptions, Vfs, VfsFile};
+use crate::accounts::Access;
+use crate::audit::TransferSource;
+use crate::hooks::{Event, EventKind};
+use crate::permissions::{PathRules, Permission, Permissions};
+use crate::quota::{QUOTA_EXCEEDED, Quota, Usage};
+use crate::server::Services;
+
+/// 账户的授权信息，打开的文件也持有一份，以便访客失效后中断传输
+#[derive(Debug)]
+struct Grant {
+    services: Services,
+    source: TransferSource,
+    access: Access,
+    rules: PathRules,
+}
+
+impl Grant {
+    fn permits(&self, granted: Permissions, permission: Permission) -> bool {
+        self.services
+            .permits(&self.source.username, granted, permission)
+    }
+
+    /// 检查共享模式和账户在该路径上的权限，访客账户失效后一律拒绝
+    fn check(&self, path: &Path, permission: Permission) -> io::Result<Permissions> {
+        let granted = self.rules.resolve(self.access, path);
+        if self.permits(granted, permission) {
+            Ok(granted)
+        } else {
+            warn!(
+                "{}: {} not permitted on {:?}",
+                self.source.username, permission, path
+            );
+            Err(io::ErrorKind::PermissionDenied.into())
+        }
+    }
+}
+
+fn quota_exceeded() -> io::Error {
+    io::Error::new(io::ErrorKind::StorageFull, QUOTA_EXCEEDED)
+}
+
+/// 以某个账户的身份访问内层存储，FTP 和 SFTP 共用
+///
+/// 检查共享模式和路径权限，写入时施加配额，删除文件时触发钩子。
+/// 策略脚本、限速和传输日志仍由各协议处理。
+#[derive(Debug)]
+pub struct AccountFs {
+    inner: Arc<dyn Vfs>,
+    grant: Arc<Grant>,
+    quota: Quota,
+}
+
+impl AccountFs {
+    pub fn new(
+        inner: Arc<dyn Vfs>,
+        services: &Services,
+        source: TransferSource,
+        access: Access,
+        rules: PathRules,
+    ) -> Self {
+        Self {
+            inner,
+            grant: Arc::new(Grant {
+                services: services.clone(),
+                source,
+                access,
+                rules,
+            }),
+            quota: Quota::default(),
+        }
+    }
+
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// 写入前检查配额，返回文件允许达到的最大长度，`None` 表示不限制
+    async fn allowance(
+        &self,
+        path: &Path,
+        existing: Option<&Metadata>,
+        options: OpenOptions,
+    ) -> io::Result<Option<u64>> {
+        if self.quota.is_unlimited() {
+            return Ok(None);
+        }
+
+        let mut usage = self.inner.usage().await?;
+        let current = match existing {
+            Some(metadata) if options.truncate => {
+                usage.bytes = usage.bytes.saturating_sub(metadata.len);
+                0
+            }
+            Some(metadata) => metadata.len,
+            None => {
+                if !self.quota.allows_new_file(&usage) {
+                    warn!(
+                        "{}: file quota exceeded, rejecting {:?}",
+                        self.grant.source.username, path
+                    );
+                    return Err(quota_exceeded());
+                }
+                0
+            }
+        };
+        Ok(self
+            .quota
+            .remaining_bytes(&usage)
+            .map(|remaining| current + remaining))
+    }
+}
+
+#[async_trait]
+impl Vfs for AccountFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.inner.metadata(path).await
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.inner.symlink_metadata(path).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        self.grant.check(path, Permission::List)?;
+        self.inner.read_dir(path).await
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let existing = self.inner.metadata(path).await.ok();
+
+        // 新建文件和覆盖已有文件分别授权，读写方式打开时还需要下载权限
+        let write = match (options.is_write(), &existing) {
+            (false, _) => None,
+            (true, Some(_)) => Some(Permission::Overwrite),
+            (true, None) => Some(Permission::Upload),
+        };
+        let read = options.read || write.is_none();
+        let mut granted = Permissions::default();
+        if read {
+            granted = self.grant.check(path, Permission::Download)?;
+        }
+        if let Some(permission) = write {
+            granted = self.grant.check(path, permission)?;
+        }
+
+        let limit = match write {
+            Some(_) => self.allowance(path, existing.as_ref(), options).await?,
+            None => None,
+        };
+        let file = self.inner.open(path, options).await?;
+        let len = match (&existing, options.truncate) {
+            (Some(metadata), false) => metadata.len,
+            _ => 0,
+        };
+        Ok(Box::new(AccountFile {
+            file,
+            grant: self.grant.clone(),
+            granted,
+            read,
+            write,
+            append: options.append,
+            limit,
+            len,
+            position: 0,
+        }))
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.grant.check(path, Permission::Mkdir)?;
+        self.inner.create_dir(path).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.grant.check(path, Permission::Delete)?;
+        self.inner.remove_file(path).await?;
+        self.grant.services.hooks.fire(
+            Event::new(EventKind::Delete, self.grant.source.clone())
+                .with_path(self.inner.display_path(path)),
+        );
+        Ok(())
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.grant.check(path, Permission::Rmdir)?;
+        self.inner.remove_dir(path).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.grant.check(from, Permission::Rename)?;
+        self.grant.check(to, Permission::Rename)?;
+        self.inner.rename(from, to).await
+    }
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.grant.check(path, Permission::Chmod)?;
+        self.inner.set_permissions(path, mode).await
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.inner.canonicalize(path).await
+    }
+
+    async fn usage(&self) -> io::Result<Usage> {
+        self.inner.usage().await
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        self.inner.display_path(path)
+    }
+}
+
+/// 通过 [`AccountFs`] 打开的文件，每次读写都重新检查权限，写入超出配额时返回 `StorageFull`
+#[derive(Debug)]
+struct AccountFile {
+    file: Box<dyn VfsFile>,
+    grant: Arc<Grant>,
+    /// 打开时路径上的权限
+    granted: Permissions,
+    read: bool,
+    write: Option<Permission>,
+    append: bool,
+    /// 文件允许达到的最大长度
+    limit: Option<u64>,
+    /// 已知的文件长度和当前位置，用于计算写入后的长度
+    len: u64,
+    position: u64,
+}
+
+impl AccountFile {
+    fn permits(&self, permission: Permission) -> io::Result<()> {
+        if self.grant.permits(self.granted, permission) {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::PermissionDenied.into())
+        }
+    }
+}
+
+#[async_trait]
+impl VfsFile for AccountFile {
+    async fn metadata(&self) -> io::Result<Metadata> {
+        self.file.metadata().await
+    }
+
+    async fn set_len(&mut self, len: u64) -> io::Result<()> {
+        let Some(permission) = self.write else {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        };
+        self.permits(permission)?;
+        if self.limit.is_some_and(|limit| len > limit.max(self.len)) {
+            return Err(quota_exceeded());
+        }
+        self.file.set_len(len).await?;
+        self.len = len;
+        Ok(())
+    }
+}
+
+impl AsyncRead for AccountFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.read {
+            return Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()));
+        }
+        this.permits(Permission::Download)?;
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.file).poll_read(cx, buf))?;
+        this.position += (buf.filled().len() - before) as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for AccountFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(permission) = this.write else {
+            return Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()));
+        };
+        this.permits(permission)?;
+
+        let start = if this.append { this.len } else { this.position };
+        if this
+            .limit
+            .is_some_and(|limit| start + buf.len() as u64 > limit.max(this.len))
+        {
+            warn!("{}: byte quota exceeded", this.grant.source.username);
+            return Poll::Ready(Err(quota_exceeded()));
+        }
+
+        let written = ready!(Pin::new(&mut this.file).poll_write(cx, buf))?;
+        this.position = start + written as u64;
+        this.len = this.len.max(this.position);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for AccountFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.get_mut().file).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        let position = ready!(Pin::new(&mut this.file).poll_complete(cx))?;
+        this.position = position;
+        Poll::Ready(Ok(position))
+    }
+}
@@ -0,0 +1,592 @@
+use std::collections::BTreeMap;
+use std::io::{self, Cursor, Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use zip::{CompressionMethod, ZipArchive};
+
+use super::{DirEntry, FileKind, Metadata, OpenOptions, Vfs, VfsFile, normalize};
+use crate::quota::Usage;
+
+/// 解压线程每次送出的字节数
+const CHUNK: usize = 64 * 1024;
+
+/// 解压线程最多领先读取方的块数
+const READ_AHEAD: usize = 4;
+
+type Chunks = mpsc::Receiver<io::Result<Vec<u8>>>;
+
+/// 支持的归档格式，按扩展名识别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+
+    /// 解压归档中的第 `index` 个条目，跳过前 `skip` 个字节后分块送出，读取方关闭后停止
+    fn unpack(
+        self,
+        archive: &Path,
+        index: usize,
+        skip: u64,
+        chunks: &mpsc::Sender<io::Result<Vec<u8>>>,
+    ) -> io::Result<()> {
+        let file = std::fs::File::open(archive)?;
+        match self {
+            Self::Zip => {
+                let mut archive = ZipArchive::new(file)?;
+                send_chunks(archive.by_index(index)?, skip, chunks)
+            }
+            Self::Tar => send_tar_entry(file, index, skip, chunks),
+            Self::TarGz => send_tar_entry(GzDecoder::new(file), index, skip, chunks),
+        }
+    }
+}
+
+/// tar 只能从头顺序读到该条目
+fn send_tar_entry<R: Read>(
+    reader: R,
+    index: usize,
+    skip: u64,
+    chunks: &mpsc::Sender<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    let entry = archive
+        .entries()?
+        .nth(index)
+        .ok_or(io::ErrorKind::NotFound)??;
+    send_chunks(entry, skip, chunks)
+}
+
+fn send_chunks<R: Read>(
+    mut reader: R,
+    skip: u64,
+    chunks: &mpsc::Sender<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    io::copy(&mut (&mut reader).take(skip), &mut io::sink())?;
+    loop {
+        let mut chunk = vec![0; CHUNK];
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        chunk.truncate(read);
+        if chunks.blocking_send(Ok(chunk)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// 文件内容在归档中的位置
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    /// 未压缩，直接读取归档文件中从该偏移开始的内容
+    Raw(u64),
+    /// 压缩的第几个条目，读取时解压
+    Packed(usize),
+}
+
+#[derive(Debug)]
+struct Entry {
+    metadata: Metadata,
+    /// 目录为 `None`
+    location: Option<Location>,
+}
+
+/// 把归档文件当作只读目录共享，目录列表和文件信息来自归档的索引
+///
+/// zip 中未压缩的条目和 tar 中的文件直接从归档读取，其他条目在读取时边解压边送出。
+#[derive(Debug)]
+pub struct ArchiveFs {
+    path: PathBuf,
+    format: Format,
+    /// 键为相对于根目录的路径，根目录为空路径
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl ArchiveFs {
+    /// 读取归档的索引，tar.gz 需要完整解压一遍
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let format = Format::detect(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Unsupported, "unsupported archive format")
+        })?;
+        let modified = std::fs::metadata(path)?.modified().ok();
+        let mut fs = Self {
+            path: path.to_path_buf(),
+            format,
+            entries: BTreeMap::new(),
+        };
+        fs.entries.insert(
+            PathBuf::new(),
+            Entry {
+                metadata: Metadata {
+                    modified,
+                    ..Metadata::virtual_dir()
+                },
+                location: None,
+            },
+        );
+
+        let file = std::fs::File::open(path)?;
+        match format {
+            Format::Zip => fs.index_zip(ZipArchive::new(file)?)?,
+            Format::Tar => {
+                let mut archive = tar::Archive::new(file);
+                fs.index_tar(archive.entries_with_seek()?, true)?;
+            }
+            Format::TarGz => {
+                let mut archive = tar::Archive::new(GzDecoder::new(file));
+                fs.index_tar(archive.entries()?, false)?;
+            }
+        }
+        Ok(fs)
+    }
+
+    fn index_zip(&mut self, mut archive: ZipArchive<std::fs::File>) -> io::Result<()> {
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index)?;
+            // 跳过试图跳出根目录的条目
+            let Some(name) = file.enclosed_name() else {
+                continue;
+            };
+            let (kind, location) = if file.is_dir() {
+                (FileKind::Dir, None)
+            } else if file.compression() == CompressionMethod::Stored && !file.encrypted() {
+                (FileKind::File, Some(Location::Raw(file.data_start())))
+            } else {
+                (FileKind::File, Some(Location::Packed(index)))
+            };
+            let metadata = Metadata {
+                kind,
+                len: file.size(),
+                mode: file.unix_mode(),
+                modified: file.last_modified().and_then(zip_time),
+                ..Default::default()
+            };
+            self.insert(&name, metadata, location);
+        }
+        Ok(())
+    }
+
+    /// 只收录普通文件和目录，`raw` 表示归档未压缩，可以直接按偏移读取
+    fn index_tar<R: Read>(&mut self, entries: tar::Entries<'_, R>, raw: bool) -> io::Result<()> {
+        for (index, entry) in entries.enumerate() {
+            let entry = entry?;
+            let header = entry.header();
+            let entry_type = header.entry_type();
+            let (kind, location) = if entry_type.is_dir() {
+                (FileKind::Dir, None)
+            } else if !entry_type.is_file() {
+                continue;
+            } else if raw {
+                (
+                    FileKind::File,
+                    Some(Location::Raw(entry.raw_file_position())),
+                )
+            } else {
+                (FileKind::File, Some(Location::Packed(index)))
+            };
+            let metadata = Metadata {
+                kind,
+                len: entry.size(),
+                mode: header.mode().ok(),
+                modified: header
+                    .mtime()
+                    .ok()
+                    .map(|mtime| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(mtime)),
+                ..Default::default()
+            };
+            self.insert(&entry.path()?, metadata, location);
+        }
+        Ok(())
+    }
+
+    /// 加入索引，归档中没有单独记录的上级目录一并补上
+    fn insert(&mut self, path: &Path, mut metadata: Metadata, location: Option<Location>) {
+        let path = normalize(path);
+        if path.as_os_str().is_empty() {
+            return;
+        }
+        for parent in path.ancestors().skip(1) {
+            self.entries
+                .entry(parent.to_path_buf())
+                .or_insert_with(|| Entry {
+                    metadata: Metadata::virtual_dir(),
+                    location: None,
+                });
+        }
+        // 归档是只读的
+        metadata.mode = metadata.mode.map(|mode| mode & 0o555);
+        self.entries.insert(path, Entry { metadata, location });
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<&Entry> {
+        self.entries
+            .get(&normalize(path))
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
+
+/// zip 中的时间没有时区，按 UTC 处理
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+    let time = NaiveDate::from_ymd_opt(time.year().into(), time.month().into(), time.day().into())?
+        .and_hms_opt(
+            time.hour().into(),
+            time.minute().into(),
+            time.second().into(),
+        )?;
+    Some(time.and_utc().into())
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "archive is read-only")
+}
+
+#[async_trait]
+impl Vfs for ArchiveFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(self.entry(path)?.metadata.clone())
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.metadata(path).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let path = normalize(path);
+        if !self.entry(&path)?.metadata.is_dir() {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        Ok(self
+            .entries
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(path.as_path()))
+            .map(|(child, entry)| DirEntry {
+                name: child
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                metadata: entry.metadata.clone(),
+            })
+            .collect())
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        if options.is_write() {
+            return Err(read_only());
+        }
+        let entry = self.entry(path)?;
+        let reader = match entry.location {
+            None => return Err(io::ErrorKind::IsADirectory.into()),
+            Some(Location::Raw(start)) => {
+                let mut file = fs::File::open(&self.path).await?;
+                file.seek(SeekFrom::Start(start)).await?;
+                Reader::Slice(Slice {
+                    file,
+                    start,
+                    len: entry.metadata.len,
+                    position: 0,
+                })
+            }
+            Some(Location::Packed(index)) => Reader::Packed(Packed {
+                archive: self.path.clone(),
+                format: self.format,
+                index,
+                len: entry.metadata.len,
+                position: 0,
+                chunks: None,
+                chunk: Cursor::default(),
+                offset: 0,
+            }),
+        };
+        Ok(Box::new(ArchiveFile {
+            reader,
+            metadata: entry.metadata.clone(),
+        }))
+    }
+
+    async fn create_dir(&self, _path: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    async fn remove_file(&self, _path: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    async fn remove_dir(&self, _path: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    async fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    async fn set_permissions(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.entry(path)?;
+        Ok(Path::new("/").join(normalize(path)))
+    }
+
+    async fn usage(&self) -> io::Result<Usage> {
+        let mut usage = Usage::default();
+        for entry in self
+            .entries
+            .values()
+            .filter(|entry| entry.metadata.is_file())
+        {
+            usage.bytes += entry.metadata.len;
+            usage.files += 1;
+        }
+        Ok(usage)
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        self.path.join(normalize(path)).display().to_string()
+    }
+}
+
+/// 归档文件中的一段，即一个未压缩的条目
+#[derive(Debug)]
+struct Slice {
+    file: fs::File,
+    start: u64,
+    len: u64,
+    /// 相对于条目开头的位置
+    position: u64,
+}
+
+impl AsyncRead for Slice {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let remaining = this.len.saturating_sub(this.position);
+        let limit = buf
+            .remaining()
+            .min(remaining.try_into().unwrap_or(usize::MAX));
+        if limit == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(limit));
+        ready!(Pin::new(&mut this.file).poll_read(cx, &mut limited))?;
+        let read = limited.filled().len();
+        buf.advance(read);
+        this.position += read as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for Slice {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => this.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        Pin::new(&mut this.file).start_seek(SeekFrom::Start(this.start + position))?;
+        this.position = position;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.file).poll_complete(cx))?;
+        Poll::Ready(Ok(this.position))
+    }
+}
+
+/// 压缩的条目，由阻塞线程顺序解压
+///
+/// 向前定位时丢弃中间的内容，向后定位时从头重新解压。
+#[derive(Debug)]
+struct Packed {
+    archive: PathBuf,
+    format: Format,
+    index: usize,
+    len: u64,
+    position: u64,
+    /// 尚未开始解压或需要重新解压时为 `None`
+    chunks: Option<Chunks>,
+    /// 当前块中尚未读取的内容
+    chunk: Cursor<Vec<u8>>,
+    /// 当前块中下一个字节在条目中的偏移
+    offset: u64,
+}
+
+impl Packed {
+    /// 从当前位置开始解压
+    fn start(&mut self) {
+        let (sender, receiver) = mpsc::channel(READ_AHEAD);
+        let (archive, format, index, skip) =
+            (self.archive.clone(), self.format, self.index, self.position);
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = format.unpack(&archive, index, skip, &sender) {
+                let _ = sender.blocking_send(Err(e));
+            }
+        });
+        self.chunks = Some(receiver);
+        self.chunk = Cursor::default();
+        self.offset = self.position;
+    }
+}
+
+impl AsyncRead for Packed {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let available = this.chunk.get_ref().len() as u64 - this.chunk.position();
+            if available > 0 {
+                if this.offset < this.position {
+                    let skipped = available.min(this.position - this.offset);
+                    this.chunk.set_position(this.chunk.position() + skipped);
+                    this.offset += skipped;
+                    continue;
+                }
+                let before = buf.filled().len();
+                ready!(Pin::new(&mut this.chunk).poll_read(cx, buf))?;
+                let read = (buf.filled().len() - before) as u64;
+                this.offset += read;
+                this.position += read;
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.chunks.is_none() {
+                this.start();
+            }
+            let chunks = this.chunks.as_mut().unwrap();
+            match ready!(chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) => this.chunk = Cursor::new(chunk),
+                Some(Err(e)) => {
+                    this.chunks = None;
+                    return Poll::Ready(Err(e));
+                }
+                // 已到条目结尾
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+impl AsyncSeek for Packed {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => this.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        if position < this.offset {
+            this.chunks = None;
+            this.chunk = Cursor::default();
+        }
+        this.position = position;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+#[derive(Debug)]
+enum Reader {
+    Slice(Slice),
+    Packed(Packed),
+}
+
+/// 打开的归档条目，只能读取
+#[derive(Debug)]
+struct ArchiveFile {
+    reader: Reader,
+    metadata: Metadata,
+}
+
+#[async_trait]
+impl VfsFile for ArchiveFile {
+    async fn metadata(&self) -> io::Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+}
+
+impl AsyncRead for ArchiveFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().reader {
+            Reader::Slice(slice) => Pin::new(slice).poll_read(cx, buf),
+            Reader::Packed(packed) => Pin::new(packed).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ArchiveFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(read_only()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for ArchiveFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match &mut self.get_mut().reader {
+            Reader::Slice(slice) => Pin::new(slice).start_seek(position),
+            Reader::Packed(packed) => Pin::new(packed).start_seek(position),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match &mut self.get_mut().reader {
+            Reader::Slice(slice) => Pin::new(slice).poll_complete(cx),
+            Reader::Packed(packed) => Pin::new(packed).poll_complete(cx),
+        }
+    }
+}
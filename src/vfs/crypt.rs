@@ -0,0 +1,876 @@
+use std::fmt;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use aes_gcm_siv::Aes256GcmSiv;
+use argon2::Argon2;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use data_encoding::BASE32_NOPAD;
+use log::warn;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, ReadBuf};
+
+use super::{DirEntry, LocalFs, Metadata, OpenOptions, Vfs, VfsFile, normalize};
+use crate::quota::Usage;
+
+/// 保存盐和口令校验值的文件，位于共享目录的根目录，不向客户端显示
+const CONFIG_FILE: &str = ".ftp-quick-crypt.json";
+const MAGIC: &[u8; 4] = b"FQC1";
+/// 每个文件随机生成的 nonce 前缀，后面接 4 字节块序号和 1 字节末块标记
+const NONCE_PREFIX: usize = 19;
+const HEADER_LEN: u64 = (MAGIC.len() + NONCE_PREFIX) as u64;
+/// 每块明文的大小
+const CHUNK: usize = 64 * 1024;
+const TAG: u64 = 16;
+const SEALED_CHUNK: u64 = CHUNK as u64 + TAG;
+/// 加密后的文件名不能超过常见文件系统的 255 字节
+const MAX_NAME: usize = 255;
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "encrypted files can only be written sequentially from the start",
+    )
+}
+
+fn corrupted() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "encrypted file is corrupted or was not written completely",
+    )
+}
+
+/// 密文长度对应的明文长度，每块多出一个认证标签
+fn plain_len(len: u64) -> u64 {
+    let body = len.saturating_sub(HEADER_LEN);
+    body.saturating_sub(body.div_ceil(SEALED_CHUNK) * TAG)
+}
+
+fn plain_metadata(mut metadata: Metadata) -> Metadata {
+    if metadata.is_file() {
+        metadata.len = plain_len(metadata.len);
+    }
+    metadata
+}
+
+#[derive(Serialize, Deserialize)]
+struct CryptConfig {
+    version: u32,
+    salt: String,
+    encrypt_names: bool,
+    /// 用口令加密的固定内容，打开时解密成功即说明口令正确
+    verifier: String,
+}
+
+/// 由口令派生的密钥
+struct Keys {
+    content: XChaCha20Poly1305,
+    /// 文件名使用确定性加密，相同的名称总是得到相同的密文，才能按名称查找
+    names: Option<Aes256GcmSiv>,
+}
+
+impl Keys {
+    fn derive(passphrase: &str, salt: &[u8], encrypt_names: bool) -> io::Result<Self> {
+        let mut key = [0u8; 64];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        let (content, names) = key.split_at(32);
+        Ok(Self {
+            content: XChaCha20Poly1305::new(content.into()),
+            names: encrypt_names.then(|| Aes256GcmSiv::new(names.into())),
+        })
+    }
+
+    fn chunk_nonce(prefix: &[u8; NONCE_PREFIX], index: u64, last: bool) -> io::Result<XNonce> {
+        let index = u32::try_from(index)
+            .map_err(|_| io::Error::new(io::ErrorKind::FileTooLarge, "file is too large"))?;
+        let mut nonce = XNonce::default();
+        nonce[..NONCE_PREFIX].copy_from_slice(prefix);
+        nonce[NONCE_PREFIX..NONCE_PREFIX + 4].copy_from_slice(&index.to_be_bytes());
+        nonce[NONCE_PREFIX + 4] = last.into();
+        Ok(nonce)
+    }
+
+    fn seal_chunk(
+        &self,
+        prefix: &[u8; NONCE_PREFIX],
+        index: u64,
+        last: bool,
+        plain: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let nonce = Self::chunk_nonce(prefix, index, last)?;
+        self.content
+            .encrypt(&nonce, plain)
+            .map_err(|_| io::Error::other("failed to encrypt chunk"))
+    }
+
+    /// 末块标记也参与认证，文件被截断时能够发现
+    fn open_chunk(
+        &self,
+        prefix: &[u8; NONCE_PREFIX],
+        index: u64,
+        last: bool,
+        sealed: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let nonce = Self::chunk_nonce(prefix, index, last)?;
+        self.content
+            .decrypt(&nonce, sealed)
+            .map_err(|_| corrupted())
+    }
+
+    /// 编码为小写的 base32，在不区分大小写的 U 盘文件系统上也不会冲突
+    fn encrypt_name(&self, name: &str) -> io::Result<String> {
+        let Some(cipher) = &self.names else {
+            return Ok(name.to_string());
+        };
+        let sealed = cipher
+            .encrypt(&Default::default(), name.as_bytes())
+            .map_err(|_| io::Error::other("failed to encrypt file name"))?;
+        let encoded = BASE32_NOPAD.encode(&sealed).to_ascii_lowercase();
+        if encoded.len() > MAX_NAME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidFilename,
+                "file name is too long to encrypt",
+            ));
+        }
+        Ok(encoded)
+    }
+
+    fn decrypt_name(&self, name: &str) -> Option<String> {
+        let Some(cipher) = &self.names else {
+            return Some(name.to_string());
+        };
+        let sealed = BASE32_NOPAD
+            .decode(name.to_ascii_uppercase().as_bytes())
+            .ok()?;
+        let plain = cipher
+            .decrypt(&Default::default(), sealed.as_slice())
+            .ok()?;
+        String::from_utf8(plain).ok()
+    }
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keys")
+            .field("encrypt_names", &self.names.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// 加密存储：文件内容分块加密后写入本地目录，下载时透明解密，可选同时加密文件名
+///
+/// 密钥由启动时输入的口令派生，不保存在磁盘上，丢失口令则无法恢复文件。
+#[derive(Debug)]
+pub struct CryptFs {
+    inner: LocalFs,
+    keys: Arc<Keys>,
+}
+
+impl CryptFs {
+    /// 打开加密目录，第一次使用时在空目录中初始化，之后必须使用相同的口令。
+    /// 是否加密文件名在初始化时确定，之后以目录中记录的为准。
+    pub fn open(root: &Path, passphrase: &str, encrypt_names: bool) -> io::Result<Self> {
+        if passphrase.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "passphrase is empty",
+            ));
+        }
+        if !std::fs::metadata(root)?.is_dir() {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+
+        let config_path = root.join(CONFIG_FILE);
+        let keys = if config_path.exists() {
+            let config: CryptConfig = serde_json::from_slice(&std::fs::read(&config_path)?)?;
+            let salt = STANDARD
+                .decode(&config.salt)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let verifier = STANDARD
+                .decode(&config.verifier)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if config.encrypt_names != encrypt_names {
+                warn!(
+                    "Encrypted directory {:?} keeps file name encryption {} from when it was created",
+                    root, config.encrypt_names
+                );
+            }
+            let keys = Keys::derive(passphrase, &salt, config.encrypt_names)?;
+            if verifier.len() < NONCE_PREFIX
+                || keys
+                    .open_chunk(
+                        verifier[..NONCE_PREFIX].try_into().unwrap(),
+                        0,
+                        true,
+                        &verifier[NONCE_PREFIX..],
+                    )
+                    .is_err()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "wrong passphrase",
+                ));
+            }
+            keys
+        } else {
+            // 已有的明文文件无法通过加密存储读取，只在空目录中初始化
+            if std::fs::read_dir(root)?.next().is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::DirectoryNotEmpty,
+                    "directory is not empty",
+                ));
+            }
+            let mut salt = [0u8; 16];
+            rand::rng().fill(&mut salt);
+            let keys = Keys::derive(passphrase, &salt, encrypt_names)?;
+            let mut prefix = [0u8; NONCE_PREFIX];
+            rand::rng().fill(&mut prefix);
+            let mut verifier = prefix.to_vec();
+            verifier.extend(keys.seal_chunk(&prefix, 0, true, MAGIC)?);
+            let config = CryptConfig {
+                version: 1,
+                salt: STANDARD.encode(salt),
+                encrypt_names,
+                verifier: STANDARD.encode(verifier),
+            };
+            std::fs::write(&config_path, serde_json::to_vec_pretty(&config)?)?;
+            keys
+        };
+
+        Ok(Self {
+            inner: LocalFs::new(root.to_path_buf()),
+            keys: Arc::new(keys),
+        })
+    }
+
+    /// 是否加密文件名，以目录创建时的设置为准
+    pub fn encrypts_names(&self) -> bool {
+        self.keys.names.is_some()
+    }
+
+    /// 客户端路径对应的磁盘路径
+    fn cipher_path(&self, path: &Path) -> io::Result<PathBuf> {
+        let relative = normalize(path);
+        if self.keys.names.is_none() && relative == Path::new(CONFIG_FILE) {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let mut cipher = PathBuf::from("/");
+        for name in relative.iter() {
+            cipher.push(self.keys.encrypt_name(&name.to_string_lossy())?);
+        }
+        Ok(cipher)
+    }
+}
+
+#[async_trait]
+impl Vfs for CryptFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = self.cipher_path(path)?;
+        self.inner.metadata(&path).await.map(plain_metadata)
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = self.cipher_path(path)?;
+        self.inner.symlink_metadata(&path).await.map(plain_metadata)
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let is_root = normalize(path).as_os_str().is_empty();
+        let entries = self.inner.read_dir(&self.cipher_path(path)?).await?;
+        Ok(entries
+            .into_iter()
+            .filter(|entry| !(is_root && entry.name == CONFIG_FILE))
+            .filter_map(|entry| match self.keys.decrypt_name(&entry.name) {
+                Some(name) => Some(DirEntry {
+                    name,
+                    metadata: plain_metadata(entry.metadata),
+                }),
+                None => {
+                    // 不是用当前口令加密的文件，例如后来直接复制进来的
+                    warn!("Skipping {} which cannot be decrypted", entry.name);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let path = self.cipher_path(path)?;
+        if options.is_write() {
+            if options.append || options.read {
+                return Err(unsupported());
+            }
+            // 不截断时是续传，已有的内容无法接着写，为了不破坏原文件在打开前拒绝
+            match self.inner.metadata(&path).await {
+                Ok(metadata) if !options.truncate && metadata.len > 0 => {
+                    return Err(unsupported());
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound && !options.create => {
+                    return Err(e);
+                }
+                _ => {}
+            }
+            let file = self
+                .inner
+                .open(
+                    &path,
+                    OpenOptions {
+                        write: true,
+                        create: true,
+                        truncate: true,
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            let mut prefix = [0u8; NONCE_PREFIX];
+            rand::rng().fill(&mut prefix);
+            let mut header = MAGIC.to_vec();
+            header.extend_from_slice(&prefix);
+            return Ok(Box::new(CryptFile::Writer(ChunkWriter {
+                file,
+                keys: self.keys.clone(),
+                prefix,
+                plain: Vec::with_capacity(CHUNK),
+                index: 0,
+                sealed: header,
+                flushed: 0,
+                written: 0,
+                finished: false,
+            })));
+        }
+
+        let mut file = self.inner.open(&path, OpenOptions::read()).await?;
+        let body = file.metadata().await?.len.saturating_sub(HEADER_LEN);
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header)
+            .await
+            .map_err(|_| corrupted())?;
+        let chunks = body.div_ceil(SEALED_CHUNK);
+        if &header[..MAGIC.len()] != MAGIC || body < TAG || body - (chunks - 1) * SEALED_CHUNK < TAG
+        {
+            return Err(corrupted());
+        }
+        Ok(Box::new(CryptFile::Reader(ChunkReader {
+            file,
+            keys: self.keys.clone(),
+            prefix: header[MAGIC.len()..].try_into().unwrap(),
+            body,
+            chunks,
+            len: plain_len(body + HEADER_LEN),
+            position: 0,
+            chunk: None,
+            load: Load::Idle,
+        })))
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir(&self.cipher_path(path)?).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(&self.cipher_path(path)?).await
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir(&self.cipher_path(path)?).await
+    }
+
+    /// 内容的密钥与路径无关，改名不需要重新加密
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner
+            .rename(&self.cipher_path(from)?, &self.cipher_path(to)?)
+            .await
+    }
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.inner
+            .set_permissions(&self.cipher_path(path)?, mode)
+            .await
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.metadata(path).await?;
+        Ok(Path::new("/").join(normalize(path)))
+    }
+
+    /// 按密文计算，比实际内容略大
+    async fn usage(&self) -> io::Result<Usage> {
+        self.inner.usage().await
+    }
+
+    /// 显示解密后的路径，文件名加密时磁盘上并没有这个路径
+    fn display_path(&self, path: &Path) -> String {
+        self.inner.display_path(path)
+    }
+}
+
+/// 正在读取的块
+#[derive(Debug)]
+enum Load {
+    Idle,
+    Seeking(u64),
+    Reading {
+        index: u64,
+        sealed: Vec<u8>,
+        filled: usize,
+    },
+}
+
+/// 按块读取并解密，可以随意定位
+#[derive(Debug)]
+struct ChunkReader {
+    file: Box<dyn VfsFile>,
+    keys: Arc<Keys>,
+    prefix: [u8; NONCE_PREFIX],
+    /// 文件头之后的密文长度
+    body: u64,
+    chunks: u64,
+    /// 明文长度
+    len: u64,
+    position: u64,
+    /// 最近解密的块及其序号
+    chunk: Option<(u64, Vec<u8>)>,
+    load: Load,
+}
+
+impl ChunkReader {
+    fn sealed_len(&self, index: u64) -> usize {
+        (self.body - index * SEALED_CHUNK).min(SEALED_CHUNK) as usize
+    }
+
+    /// 读取并解密一块，返回的可能是之前已经开始读取的块
+    fn poll_load(&mut self, cx: &mut Context<'_>, index: u64) -> Poll<io::Result<(u64, Vec<u8>)>> {
+        loop {
+            match &mut self.load {
+                Load::Idle => {
+                    Pin::new(&mut self.file)
+                        .start_seek(SeekFrom::Start(HEADER_LEN + index * SEALED_CHUNK))?;
+                    self.load = Load::Seeking(index);
+                }
+                Load::Seeking(seeking) => {
+                    let seeking = *seeking;
+                    ready!(Pin::new(&mut self.file).poll_complete(cx))?;
+                    self.load = Load::Reading {
+                        index: seeking,
+                        sealed: vec![0; self.sealed_len(seeking)],
+                        filled: 0,
+                    };
+                }
+                Load::Reading {
+                    index: reading,
+                    sealed,
+                    filled,
+                } => {
+                    while *filled < sealed.len() {
+                        let mut buf = ReadBuf::new(&mut sealed[*filled..]);
+                        ready!(Pin::new(&mut self.file).poll_read(cx, &mut buf))?;
+                        if buf.filled().is_empty() {
+                            return Poll::Ready(Err(corrupted()));
+                        }
+                        *filled += buf.filled().len();
+                    }
+                    let reading = *reading;
+                    let sealed = std::mem::take(sealed);
+                    self.load = Load::Idle;
+                    let last = reading + 1 == self.chunks;
+                    let plain = self.keys.open_chunk(&self.prefix, reading, last, &sealed)?;
+                    return Poll::Ready(Ok((reading, plain)));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for ChunkReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.position >= this.len || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            let index = this.position / CHUNK as u64;
+            if let Some((loaded, plain)) = &this.chunk
+                && *loaded == index
+            {
+                let offset = (this.position % CHUNK as u64) as usize;
+                let available = plain.get(offset..).ok_or_else(corrupted)?;
+                let n = buf.remaining().min(available.len());
+                buf.put_slice(&available[..n]);
+                this.position += n as u64;
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(this.poll_load(cx, index)) {
+                Ok(chunk) => this.chunk = Some(chunk),
+                Err(e) => {
+                    this.load = Load::Idle;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncSeek for ChunkReader {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        this.position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => this.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+/// 按块加密后顺序写入，关闭时写入末块
+#[derive(Debug)]
+struct ChunkWriter {
+    file: Box<dyn VfsFile>,
+    keys: Arc<Keys>,
+    prefix: [u8; NONCE_PREFIX],
+    /// 尚未加密的明文，不超过一块
+    plain: Vec<u8>,
+    index: u64,
+    /// 等待写入文件的文件头或密文
+    sealed: Vec<u8>,
+    flushed: usize,
+    written: u64,
+    finished: bool,
+}
+
+impl ChunkWriter {
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        self.sealed = self
+            .keys
+            .seal_chunk(&self.prefix, self.index, last, &self.plain)?;
+        self.flushed = 0;
+        self.plain.clear();
+        self.index += 1;
+        Ok(())
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.flushed < self.sealed.len() {
+            let n = ready!(Pin::new(&mut self.file).poll_write(cx, &self.sealed[self.flushed..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.flushed += n;
+        }
+        self.sealed.clear();
+        self.flushed = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ChunkWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // 有后续数据时才能确定已满的块不是末块
+        if this.plain.len() == CHUNK {
+            this.seal(false)?;
+            ready!(this.poll_drain(cx))?;
+        }
+        let n = buf.len().min(CHUNK - this.plain.len());
+        this.plain.extend_from_slice(&buf[..n]);
+        this.written += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.finished {
+            ready!(this.poll_drain(cx))?;
+            this.seal(true)?;
+            this.finished = true;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.file).poll_shutdown(cx)
+    }
+}
+
+/// 打开的加密文件，读取时解密，写入时加密
+#[derive(Debug)]
+enum CryptFile {
+    Reader(ChunkReader),
+    Writer(ChunkWriter),
+}
+
+#[async_trait]
+impl VfsFile for CryptFile {
+    async fn metadata(&self) -> io::Result<Metadata> {
+        match self {
+            Self::Reader(reader) => Ok(Metadata {
+                len: reader.len,
+                ..reader.file.metadata().await?
+            }),
+            Self::Writer(writer) => Ok(Metadata {
+                len: writer.written,
+                ..writer.file.metadata().await?
+            }),
+        }
+    }
+}
+
+impl AsyncRead for CryptFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Reader(reader) => Pin::new(reader).poll_read(cx, buf),
+            Self::Writer(_) => Poll::Ready(Err(unsupported())),
+        }
+    }
+}
+
+impl AsyncWrite for CryptFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Reader(_) => Poll::Ready(Err(io::ErrorKind::PermissionDenied.into())),
+            Self::Writer(writer) => Pin::new(writer).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Reader(_) => Poll::Ready(Ok(())),
+            Self::Writer(writer) => Pin::new(writer).poll_flush(cx),
+        }
+    }
+
+    /// 末块在关闭时写入，没有关闭的文件读取时会报告不完整
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Reader(_) => Poll::Ready(Ok(())),
+            Self::Writer(writer) => Pin::new(writer).poll_shutdown(cx),
+        }
+    }
+}
+
+impl AsyncSeek for CryptFile {
+    /// 写入时只允许定位到已写入的末尾，即顺序写入
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.get_mut() {
+            Self::Reader(reader) => Pin::new(reader).start_seek(position),
+            Self::Writer(writer) => match position {
+                SeekFrom::Start(offset) if offset == writer.written => Ok(()),
+                SeekFrom::Current(0) | SeekFrom::End(0) => Ok(()),
+                _ => Err(unsupported()),
+            },
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.get_mut() {
+            Self::Reader(reader) => Pin::new(reader).poll_complete(cx),
+            Self::Writer(writer) => Poll::Ready(Ok(writer.written)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// 每个测试使用自己的空目录
+    fn empty_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ftp-quick-crypt-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn write(fs: &CryptFs, path: &str, data: &[u8]) {
+        let options = OpenOptions {
+            write: true,
+            create: true,
+            truncate: true,
+            ..Default::default()
+        };
+        let mut file = fs.open(Path::new(path), options).await.unwrap();
+        file.write_all(data).await.unwrap();
+        file.shutdown().await.unwrap();
+    }
+
+    async fn read(fs: &CryptFs, path: &str) -> io::Result<Vec<u8>> {
+        let mut file = fs.open(Path::new(path), OpenOptions::read()).await?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn round_trip_at_chunk_boundaries() {
+        let dir = empty_dir("round-trip");
+        let fs = CryptFs::open(&dir, "secret", false).unwrap();
+        for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 2 * CHUNK] {
+            let name = format!("{}.bin", len);
+            let data = content(len);
+            write(&fs, &name, &data).await;
+            assert_eq!(read(&fs, &name).await.unwrap(), data, "length {}", len);
+            let metadata = fs.metadata(Path::new(&name)).await.unwrap();
+            assert_eq!(metadata.len, len as u64);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn seek_into_later_chunk() {
+        let dir = empty_dir("seek");
+        let fs = CryptFs::open(&dir, "secret", false).unwrap();
+        let data = content(2 * CHUNK + 100);
+        write(&fs, "file.bin", &data).await;
+        let mut file = fs
+            .open(Path::new("file.bin"), OpenOptions::read())
+            .await
+            .unwrap();
+        let offset = CHUNK as u64 + 10;
+        file.seek(SeekFrom::Start(offset)).await.unwrap();
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, &data[offset as usize..]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncation_at_chunk_boundary_is_detected() {
+        let dir = empty_dir("truncate");
+        let fs = CryptFs::open(&dir, "secret", false).unwrap();
+        write(&fs, "file.bin", &content(2 * CHUNK)).await;
+        // 去掉整个末块后剩下的仍是完整的块，只有末块标记能发现
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("file.bin"))
+            .unwrap();
+        file.set_len(HEADER_LEN + SEALED_CHUNK).unwrap();
+        drop(file);
+        let e = read(&fs, "file.bin").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unfinished_file_is_detected() {
+        let dir = empty_dir("unfinished");
+        let fs = CryptFs::open(&dir, "secret", false).unwrap();
+        let options = OpenOptions {
+            write: true,
+            create: true,
+            ..Default::default()
+        };
+        let mut file = fs.open(Path::new("file.bin"), options).await.unwrap();
+        file.write_all(&content(CHUNK + 1)).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        let e = read(&fs, "file.bin").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let dir = empty_dir("passphrase");
+        CryptFs::open(&dir, "secret", false).unwrap();
+        let e = CryptFs::open(&dir, "guess", false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert!(CryptFs::open(&dir, "secret", false).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn non_empty_directory_is_not_initialized() {
+        let dir = empty_dir("non-empty");
+        std::fs::write(dir.join("plain.txt"), b"plain").unwrap();
+        let e = CryptFs::open(&dir, "secret", false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::DirectoryNotEmpty);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_names_are_encrypted() {
+        let dir = empty_dir("names");
+        let fs = CryptFs::open(&dir, "secret", true).unwrap();
+        fs.create_dir(Path::new("Docs")).await.unwrap();
+        write(&fs, "Docs/报告.txt", b"hello").await;
+
+        // 磁盘上只有加密后的小写名称
+        let on_disk: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != CONFIG_FILE)
+            .collect();
+        assert_eq!(on_disk.len(), 1);
+        assert_ne!(on_disk[0], "Docs");
+        assert_eq!(on_disk[0], on_disk[0].to_ascii_lowercase());
+
+        let entries = fs.read_dir(Path::new("/")).await.unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["Docs"]);
+        let entries = fs.read_dir(Path::new("Docs")).await.unwrap();
+        assert_eq!(entries[0].name, "报告.txt");
+        assert_eq!(read(&fs, "/Docs/报告.txt").await.unwrap(), b"hello");
+
+        // 之后打开时沿用创建时的设置
+        let reopened = CryptFs::open(&dir, "secret", false).unwrap();
+        assert!(reopened.encrypts_names());
+        assert_eq!(read(&reopened, "Docs/报告.txt").await.unwrap(), b"hello");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn config_file_is_hidden() {
+        let dir = empty_dir("config");
+        let fs = CryptFs::open(&dir, "secret", false).unwrap();
+        assert!(!fs.encrypts_names());
+        assert!(fs.read_dir(Path::new("/")).await.unwrap().is_empty());
+        let e = read(&fs, CONFIG_FILE).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use log::warn;
+use russh_sftp::protocol::StatusCode;
+use tokio::fs;
+
+use super::{DirEntry, Metadata, OpenOptions, Vfs, VfsFile};
+use crate::quota::{self, Usage};
+use crate::sftp::utils::path_resolver::PathResolver;
+
+/// 本地磁盘后端，所有路径限制在根目录内
+#[derive(Debug)]
+pub struct LocalFs {
+    resolver: PathResolver,
+}
+
+impl LocalFs {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            resolver: PathResolver::new(root),
+        }
+    }
+
+    fn root(&self) -> &Path {
+        self.resolver.get_root_dir()
+    }
+
+    pub(super) fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        self.resolver
+            .resolve_path(&path.to_string_lossy())
+            .map_err(|status| match status {
+                StatusCode::PermissionDenied => io::Error::from(io::ErrorKind::PermissionDenied),
+                _ => io::Error::from(io::ErrorKind::NotFound),
+            })
+    }
+}
+
+#[async_trait]
+impl Vfs for LocalFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(self.resolve(path)?).await.map(Metadata::from)
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(self.resolve(path)?)
+            .await
+            .map(Metadata::from)
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut read_dir = fs::read_dir(self.resolve(path)?).await?;
+        let mut entries = Vec::new();
+        while let Some(entry) = read_dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let metadata = match entry.metadata().await {
+                Ok(metadata) => Metadata::from(metadata),
+                Err(e) => {
+                    // 读取失败时按普通文件显示
+                    warn!("Failed to get metadata for {}: {}", name, e);
+                    Metadata::default()
+                }
+            };
+            entries.push(DirEntry { name, metadata });
+        }
+        Ok(entries)
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let resolved = self.resolve(path)?;
+        if options.is_write() {
+            if resolved.parent().is_some_and(|parent| !parent.exists()) {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+        } else if !fs::metadata(&resolved).await?.is_file() {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+
+        let file = fs::OpenOptions::new()
+            .read(options.read || !options.is_write())
+            .write(options.write)
+            .create(options.create)
+            .truncate(options.truncate)
+            .append(options.append)
+            .open(&resolved)
+            .await?;
+        Ok(Box::new(file))
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(self.resolve(path)?).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(self.resolve(path)?).await
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let resolved = self.resolve(path)?;
+        // 不允许删除根目录
+        if resolved == self.root() {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        fs::remove_dir(resolved).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let from = self.resolve(from)?;
+        let to = self.resolve(to)?;
+        if to.exists() {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        fs::rename(from, to).await
+    }
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let resolved = self.resolve(path)?;
+        let mut permissions = fs::metadata(&resolved).await?.permissions();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            permissions.set_mode(mode & 0o7777);
+        }
+        #[cfg(not(unix))]
+        permissions.set_readonly(mode & 0o200 == 0);
+        fs::set_permissions(resolved, permissions).await
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let canonical = fs::canonicalize(self.resolve(path)?).await?;
+        let relative = canonical.strip_prefix(self.root()).unwrap_or(Path::new(""));
+        Ok(Path::new("/").join(relative))
+    }
+
+    async fn usage(&self) -> io::Result<Usage> {
+        quota::usage(self.root().to_path_buf()).await
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        let relative = path.strip_prefix("/").unwrap_or(path);
+        self.root().join(relative).to_string_lossy().into_owned()
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::BTreeMap;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use super::{DirEntry, FileKind, Metadata, OpenOptions, Vfs, VfsFile, normalize};
+use crate::quota::Usage;
+
+/// 文件内容，打开的文件和目录树共享同一份
+#[derive(Debug)]
+struct Content {
+    bytes: Vec<u8>,
+    modified: SystemTime,
+    /// 删除后仍打开的文件不再计入占用
+    linked: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File(Arc<Mutex<Content>>),
+    Dir(SystemTime),
+}
+
+#[derive(Debug)]
+struct Tree {
+    /// 键为相对于根目录的路径，根目录为空路径
+    nodes: BTreeMap<PathBuf, (Node, u32)>,
+    used: u64,
+}
+
+impl Tree {
+    fn node(&self, path: &Path) -> io::Result<&(Node, u32)> {
+        self.nodes
+            .get(path)
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    /// 新建项之前检查上级目录是否存在
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent().map(|parent| self.node(parent)) {
+            Some(Ok((Node::Dir(_), _))) => Ok(()),
+            Some(Ok(_)) => Err(io::ErrorKind::NotADirectory.into()),
+            Some(Err(e)) => Err(e),
+            // 根目录
+            None => Err(io::ErrorKind::AlreadyExists.into()),
+        }
+    }
+
+    fn children<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> impl Iterator<Item = (&'a PathBuf, &'a (Node, u32))> {
+        self.nodes
+            .iter()
+            .filter(move |(child, _)| child.parent() == Some(path))
+    }
+}
+
+fn metadata(node: &Node, mode: u32) -> Metadata {
+    let (kind, len, modified) = match node {
+        Node::File(content) => {
+            let content = content.lock().unwrap();
+            (FileKind::File, content.bytes.len() as u64, content.modified)
+        }
+        Node::Dir(modified) => (FileKind::Dir, 0, *modified),
+    };
+    Metadata {
+        kind,
+        len,
+        mode: Some(mode),
+        nlink: Some(1),
+        modified: Some(modified),
+        ..Default::default()
+    }
+}
+
+fn storage_full() -> io::Error {
+    io::Error::new(io::ErrorKind::StorageFull, "memory storage is full")
+}
+
+/// 内存中的文件系统，内容不写入磁盘，随服务器停止一起丢弃
+///
+/// 所有文件的总大小不能超过 `capacity`，超出时写入返回 `StorageFull`。
+#[derive(Debug)]
+pub struct MemoryFs {
+    tree: Arc<Mutex<Tree>>,
+    capacity: Option<u64>,
+}
+
+impl MemoryFs {
+    pub fn new(capacity: Option<u64>) -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::new(), (Node::Dir(SystemTime::now()), 0o755));
+        Self {
+            tree: Arc::new(Mutex::new(Tree { nodes, used: 0 })),
+            capacity,
+        }
+    }
+
+    fn tree(&self) -> std::sync::MutexGuard<'_, Tree> {
+        self.tree.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl Vfs for MemoryFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let tree = self.tree();
+        let (node, mode) = tree.node(&normalize(path))?;
+        Ok(metadata(node, *mode))
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.metadata(path).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let path = normalize(path);
+        let tree = self.tree();
+        if let (Node::File(_), _) = tree.node(&path)? {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        Ok(tree
+            .children(&path)
+            .map(|(child, (node, mode))| DirEntry {
+                name: child
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                metadata: metadata(node, *mode),
+            })
+            .collect())
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let path = normalize(path);
+        let mut tree = self.tree();
+        let content = match tree.nodes.get(&path) {
+            Some((Node::Dir(_), _)) => return Err(io::ErrorKind::IsADirectory.into()),
+            Some((Node::File(content), _)) => content.clone(),
+            None if options.create => {
+                tree.check_parent(&path)?;
+                let content = Arc::new(Mutex::new(Content {
+                    bytes: Vec::new(),
+                    modified: SystemTime::now(),
+                    linked: true,
+                }));
+                tree.nodes
+                    .insert(path, (Node::File(content.clone()), 0o644));
+                content
+            }
+            None => return Err(io::ErrorKind::NotFound.into()),
+        };
+
+        if options.truncate {
+            let mut content = content.lock().unwrap();
+            tree.used -= content.bytes.len() as u64;
+            content.bytes.clear();
+            content.modified = SystemTime::now();
+        }
+        Ok(Box::new(MemoryFile {
+            content,
+            tree: self.tree.clone(),
+            capacity: self.capacity,
+            position: 0,
+            readable: options.read || !options.is_write(),
+            writable: options.is_write(),
+            append: options.append,
+        }))
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree();
+        if tree.nodes.contains_key(&path) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        tree.check_parent(&path)?;
+        tree.nodes
+            .insert(path, (Node::Dir(SystemTime::now()), 0o755));
+        Ok(())
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree();
+        let Node::File(content) = &tree.node(&path)?.0 else {
+            return Err(io::ErrorKind::IsADirectory.into());
+        };
+        let len = {
+            let mut content = content.lock().unwrap();
+            content.linked = false;
+            content.bytes.len() as u64
+        };
+        tree.nodes.remove(&path);
+        tree.used -= len;
+        Ok(())
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree();
+        if path.as_os_str().is_empty() {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        if let (Node::File(_), _) = tree.node(&path)? {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        if tree.children(&path).next().is_some() {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+        tree.nodes.remove(&path);
+        Ok(())
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut tree = self.tree();
+        tree.node(&from)?;
+        if from.as_os_str().is_empty() || to.starts_with(&from) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        if tree.nodes.contains_key(&to) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        tree.check_parent(&to)?;
+
+        // 目录连同其下所有内容一起移动
+        let moved: Vec<PathBuf> = tree
+            .nodes
+            .keys()
+            .filter(|path| path.starts_with(&from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let node = tree.nodes.remove(&path).unwrap();
+            let relative = path.strip_prefix(&from).unwrap_or(Path::new(""));
+            let target = if relative.as_os_str().is_empty() {
+                to.clone()
+            } else {
+                to.join(relative)
+            };
+            tree.nodes.insert(target, node);
+        }
+        Ok(())
+    }
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree();
+        let node = tree.nodes.get_mut(&path).ok_or(io::ErrorKind::NotFound)?;
+        node.1 = mode & 0o7777;
+        Ok(())
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path);
+        self.tree().node(&path)?;
+        Ok(Path::new("/").join(path))
+    }
+
+    async fn usage(&self) -> io::Result<Usage> {
+        let tree = self.tree();
+        let files = tree
+            .nodes
+            .values()
+            .filter(|(node, _)| matches!(node, Node::File(_)))
+            .count();
+        Ok(Usage {
+            bytes: tree.used,
+            files: files as u64,
+        })
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        format!("memory:/{}", normalize(path).display())
+    }
+}
+
+/// 打开的内存文件
+#[derive(Debug)]
+struct MemoryFile {
+    content: Arc<Mutex<Content>>,
+    tree: Arc<Mutex<Tree>>,
+    capacity: Option<u64>,
+    position: u64,
+    readable: bool,
+    writable: bool,
+    append: bool,
+}
+
+#[async_trait]
+impl VfsFile for MemoryFile {
+    async fn metadata(&self) -> io::Result<Metadata> {
+        let content = self.content.lock().unwrap();
+        Ok(Metadata {
+            kind: FileKind::File,
+            len: content.bytes.len() as u64,
+            mode: Some(0o644),
+            nlink: Some(1),
+            modified: Some(content.modified),
+            ..Default::default()
+        })
+    }
+
+    async fn set_len(&mut self, len: u64) -> io::Result<()> {
+        if !self.writable {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let mut tree = self.tree.lock().unwrap();
+        let mut content = self.content.lock().unwrap();
+        let current = content.bytes.len() as u64;
+        if content.linked {
+            let growth = len.saturating_sub(current);
+            if self
+                .capacity
+                .is_some_and(|capacity| tree.used + growth > capacity)
+            {
+                return Err(storage_full());
+            }
+            tree.used = tree.used + growth - current.saturating_sub(len);
+        }
+        content.bytes.resize(len as usize, 0);
+        content.modified = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl AsyncRead for MemoryFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.readable {
+            return Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()));
+        }
+        let content = this.content.lock().unwrap();
+        let start = (this.position as usize).min(content.bytes.len());
+        let len = buf.remaining().min(content.bytes.len() - start);
+        buf.put_slice(&content.bytes[start..start + len]);
+        this.position += len as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MemoryFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.writable {
+            return Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()));
+        }
+
+        // 先锁目录树再锁内容，与删除文件时的顺序一致
+        let mut tree = this.tree.lock().unwrap();
+        let mut content = this.content.lock().unwrap();
+        let len = content.bytes.len();
+        let start = if this.append {
+            len
+        } else {
+            this.position as usize
+        };
+        let end = start + buf.len();
+        let growth = end.saturating_sub(len) as u64;
+        if content.linked {
+            if this
+                .capacity
+                .is_some_and(|capacity| tree.used + growth > capacity)
+            {
+                return Poll::Ready(Err(storage_full()));
+            }
+            tree.used += growth;
+        }
+
+        if end > len {
+            content.bytes.resize(end, 0);
+        }
+        content.bytes[start..end].copy_from_slice(buf);
+        content.modified = SystemTime::now();
+        this.position = end as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for MemoryFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let len = this.content.lock().unwrap().bytes.len() as u64;
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        };
+        this.position = position.ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    const CAPACITY: u64 = 1024 * 1024;
+
+    async fn create(fs: &MemoryFs, path: &str) -> Box<dyn VfsFile> {
+        let options = OpenOptions {
+            write: true,
+            create: true,
+            ..Default::default()
+        };
+        fs.open(Path::new(path), options).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn writes_beyond_capacity_fail() {
+        let fs = MemoryFs::new(Some(CAPACITY));
+        let mut file = create(&fs, "full.bin").await;
+        file.write_all(&vec![0; CAPACITY as usize]).await.unwrap();
+        let e = file.write_all(&[0]).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::StorageFull);
+        let e = create(&fs, "other.bin").await.set_len(1).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::StorageFull);
+    }
+
+    #[tokio::test]
+    async fn removing_and_truncating_free_space() {
+        let fs = MemoryFs::new(Some(CAPACITY));
+        let mut file = create(&fs, "a.bin").await;
+        file.write_all(&vec![0; CAPACITY as usize]).await.unwrap();
+        file.set_len(CAPACITY / 2).await.unwrap();
+        drop(file);
+
+        let mut file = create(&fs, "b.bin").await;
+        file.write_all(&vec![0; (CAPACITY / 2) as usize])
+            .await
+            .unwrap();
+        assert!(file.write_all(&[0]).await.is_err());
+
+        fs.remove_file(Path::new("a.bin")).await.unwrap();
+        file.write_all(&[0]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unlimited_without_capacity() {
+        let fs = MemoryFs::new(None);
+        let mut file = create(&fs, "big.bin").await;
+        file.write_all(&vec![0; 2 * CAPACITY as usize])
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+mod account;
+mod archive;
+mod crypt;
+mod local;
+mod memory;
+mod mount;
+mod overlay;
+mod s3;
+mod selection;
+mod sftp;
+
+use std::fmt::Debug;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
+
+use crate::quota::Usage;
+use crate::server::Services;
+
+pub use account::AccountFs;
+pub use archive::ArchiveFs;
+pub use crypt::CryptFs;
+pub use local::LocalFs;
+pub use memory::MemoryFs;
+pub use mount::{MountFs, MountPoint};
+pub use overlay::OverlayFs;
+pub use s3::S3Fs;
+pub use selection::SelectionFs;
+pub use sftp::SftpFs;
+
+/// 文件类型
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileKind {
+    #[default]
+    File,
+    Dir,
+    Symlink,
+}
+
+/// 与后端无关的文件元数据，没有的信息为 `None`
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub kind: FileKind,
+    pub len: u64,
+    /// Unix 权限位，不含文件类型
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub nlink: Option<u64>,
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    /// 后端中没有实际目录时显示的只读目录，例如挂载点的上级目录
+    pub fn virtual_dir() -> Self {
+        Self {
+            kind: FileKind::Dir,
+            mode: Some(0o555),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<std::fs::Metadata> for Metadata {
+    fn from(metadata: std::fs::Metadata) -> Self {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else {
+            FileKind::File
+        };
+
+        #[cfg(unix)]
+        let (mode, uid, gid, nlink) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                Some(metadata.mode() & 0o7777),
+                Some(metadata.uid()),
+                Some(metadata.gid()),
+                Some(metadata.nlink()),
+            )
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid, nlink) = (None, None, None, None);
+
+        Self {
+            kind,
+            len: metadata.len(),
+            mode,
+            uid,
+            gid,
+            nlink,
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+        }
+    }
+}
+
+/// 目录项
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// 打开文件的方式，与 SFTP 的 `pflags` 对应
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    pub read: bool,
+    pub write: bool,
+    pub create: bool,
+    pub truncate: bool,
+    pub append: bool,
+}
+
+impl OpenOptions {
+    pub fn read() -> Self {
+        Self {
+            read: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.write || self.create || self.truncate || self.append
+    }
+}
+
+/// 打开的文件，支持随机读写
+#[async_trait]
+pub trait VfsFile: AsyncRead + AsyncWrite + AsyncSeek + Debug + Send + Sync + Unpin {
+    async fn metadata(&self) -> io::Result<Metadata>;
+
+    /// 截断或扩展文件，不支持的后端返回 `Unsupported`
+    async fn set_len(&mut self, _len: u64) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[async_trait]
+impl VfsFile for tokio::fs::File {
+    async fn metadata(&self) -> io::Result<Metadata> {
+        tokio::fs::File::metadata(self).await.map(Metadata::from)
+    }
+
+    async fn set_len(&mut self, len: u64) -> io::Result<()> {
+        tokio::fs::File::set_len(self, len).await
+    }
+}
+
+/// 虚拟文件系统，各协议通过它访问共享内容，可以有不同的后端或在外层包装
+///
+/// 路径均为客户端看到的路径，以 `/` 开头或相对于根目录，后端负责将其限制在自身范围内。
+#[async_trait]
+pub trait Vfs: Debug + Send + Sync {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// 不跟随符号链接的元数据
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>>;
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// 目标已存在时失败
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()>;
+
+    /// 规范化后的路径，以 `/` 开头
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// 所有文件的总大小和数量，用于配额
+    async fn usage(&self) -> io::Result<Usage>;
+
+    /// 日志和钩子中显示的路径，本地后端为实际路径
+    fn display_path(&self, path: &Path) -> String;
+}
+
+/// 共享的内容：存储后端、选中的文件或根目录，启用叠加层时该用户的修改叠加在上面，再加上界面上配置的挂载点
+pub fn share(root: PathBuf, services: &Services, username: &str) -> Arc<dyn Vfs> {
+    let mut base: Arc<dyn Vfs> = if let Some(backend) = &services.backend {
+        backend.clone()
+    } else if !services.files.is_empty() {
+        Arc::new(SelectionFs::new(&services.files))
+    } else {
+        Arc::new(LocalFs::new(root))
+    };
+    if let Some(overlay) = &services.overlay {
+        base = Arc::new(OverlayFs::new(base, overlay, username));
+    }
+    if services.mounts.is_empty() {
+        return base;
+    }
+    let fs = services
+        .mounts
+        .iter()
+        .fold(MountFs::new(base), |fs, mount| {
+            fs.mount(
+                mount.path.clone(),
+                Arc::new(LocalFs::new(mount.target.clone())),
+                mount.read_only,
+            )
+        });
+    Arc::new(fs)
+}
+
+/// 客户端路径去掉根目录和 `.`、`..` 后的相对形式
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+    normalized
+}
@@ -0,0 +1,255 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::{DirEntry, Metadata, OpenOptions, Vfs, VfsFile, normalize};
+use crate::quota::Usage;
+
+/// 挂载点：把本机目录发布到共享中的某个虚拟路径下
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountPoint {
+    /// 相对于共享根目录的虚拟路径，例如 `builds`
+    pub path: PathBuf,
+    pub target: PathBuf,
+    pub read_only: bool,
+}
+
+impl MountPoint {
+    pub fn new(path: &str, target: PathBuf, read_only: bool) -> Result<Self, String> {
+        let path = normalize(Path::new(path.trim()));
+        if path.as_os_str().is_empty() {
+            return Err("挂载路径不能为根目录".to_string());
+        }
+        Ok(Self {
+            path,
+            target,
+            read_only,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Mounted {
+    path: PathBuf,
+    fs: Arc<dyn Vfs>,
+    read_only: bool,
+}
+
+/// 路径所在的后端
+struct Route<'a> {
+    fs: &'a dyn Vfs,
+    /// 挂载点的虚拟路径，根目录的后端为空
+    prefix: &'a Path,
+    /// 在该后端中的路径
+    path: PathBuf,
+    read_only: bool,
+}
+
+impl Route<'_> {
+    /// 路径本身就是挂载点
+    fn is_mount_point(&self) -> bool {
+        !self.prefix.as_os_str().is_empty() && self.path == Path::new("/")
+    }
+}
+
+/// 把多个后端组合到同一个虚拟根目录下，不在任何挂载点内的路径交给根目录的后端
+///
+/// 挂载点可以嵌套，最长匹配的生效；挂载点本身不能删除或改名，只读挂载点内不能修改。
+#[derive(Debug)]
+pub struct MountFs {
+    base: Arc<dyn Vfs>,
+    mounts: Vec<Mounted>,
+}
+
+impl MountFs {
+    pub fn new(base: Arc<dyn Vfs>) -> Self {
+        Self {
+            base,
+            mounts: Vec::new(),
+        }
+    }
+
+    /// 把后端挂载到虚拟路径下，路径相对于根目录
+    pub fn mount(mut self, path: PathBuf, fs: Arc<dyn Vfs>, read_only: bool) -> Self {
+        self.mounts.push(Mounted {
+            path: normalize(&path),
+            fs,
+            read_only,
+        });
+        self
+    }
+
+    fn route(&self, path: &Path) -> Route<'_> {
+        let path = normalize(path);
+        match self
+            .mounts
+            .iter()
+            .filter(|mount| path.starts_with(&mount.path))
+            .max_by_key(|mount| mount.path.components().count())
+        {
+            Some(mount) => Route {
+                fs: mount.fs.as_ref(),
+                prefix: &mount.path,
+                path: Path::new("/").join(path.strip_prefix(&mount.path).unwrap_or(&path)),
+                read_only: mount.read_only,
+            },
+            None => Route {
+                fs: self.base.as_ref(),
+                prefix: Path::new(""),
+                path: Path::new("/").join(path),
+                read_only: false,
+            },
+        }
+    }
+
+    /// 路径下直接可见的挂载点名称，例如挂载了 `a/b` 时根目录下有 `a`
+    fn mounted_children(&self, path: &Path) -> BTreeSet<String> {
+        let path = normalize(path);
+        self.mounts
+            .iter()
+            .filter_map(|mount| mount.path.strip_prefix(&path).ok())
+            .filter_map(|rest| rest.components().next())
+            .map(|name| name.as_os_str().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// 修改前检查：只读挂载点内、挂载点本身和包含挂载点的目录都不能修改
+    fn writable(&self, path: &Path) -> io::Result<Route<'_>> {
+        let route = self.route(path);
+        if route.read_only || route.is_mount_point() || !self.mounted_children(path).is_empty() {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        Ok(route)
+    }
+}
+
+#[async_trait]
+impl Vfs for MountFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let route = self.route(path);
+        match route.fs.metadata(&route.path).await {
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    && !self.mounted_children(path).is_empty() =>
+            {
+                Ok(Metadata::virtual_dir())
+            }
+            result => result,
+        }
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let route = self.route(path);
+        match route.fs.symlink_metadata(&route.path).await {
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    && !self.mounted_children(path).is_empty() =>
+            {
+                Ok(Metadata::virtual_dir())
+            }
+            result => result,
+        }
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let children = self.mounted_children(path);
+        let route = self.route(path);
+        let mut entries = match route.fs.read_dir(&route.path).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !children.is_empty() => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        // 挂载点遮盖同名的文件或目录
+        entries.retain(|entry| !children.contains(&entry.name));
+        for name in children {
+            let metadata = self
+                .metadata(&path.join(&name))
+                .await
+                .unwrap_or_else(|_| Metadata::virtual_dir());
+            entries.push(DirEntry { name, metadata });
+        }
+        Ok(entries)
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let route = if options.is_write() {
+            self.writable(path)?
+        } else {
+            self.route(path)
+        };
+        route.fs.open(&route.path, options).await
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let route = self.writable(path)?;
+        route.fs.create_dir(&route.path).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let route = self.writable(path)?;
+        route.fs.remove_file(&route.path).await
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let route = self.writable(path)?;
+        route.fs.remove_dir(&route.path).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let from = self.writable(from)?;
+        let to = self.writable(to)?;
+        if !std::ptr::addr_eq(from.fs, to.fs) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot rename across mount points",
+            ));
+        }
+        from.fs.rename(&from.path, &to.path).await
+    }
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let route = self.writable(path)?;
+        route.fs.set_permissions(&route.path, mode).await
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let route = self.route(path);
+        match route.fs.canonicalize(&route.path).await {
+            Ok(canonical) => {
+                let mut result = Path::new("/").join(route.prefix);
+                let relative = canonical.strip_prefix("/").unwrap_or(&canonical);
+                if !relative.as_os_str().is_empty() {
+                    result.push(relative);
+                }
+                Ok(result)
+            }
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    && !self.mounted_children(path).is_empty() =>
+            {
+                Ok(Path::new("/").join(normalize(path)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 根目录和可写挂载点的占用之和
+    async fn usage(&self) -> io::Result<Usage> {
+        let mut total = self.base.usage().await?;
+        for mount in self.mounts.iter().filter(|mount| !mount.read_only) {
+            let usage = mount.fs.usage().await?;
+            total.bytes += usage.bytes;
+            total.files += usage.files;
+        }
+        Ok(total)
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        let route = self.route(path);
+        route.fs.display_path(&route.path)
+    }
+}
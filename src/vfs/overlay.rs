@@ -0,0 +1,383 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use log::warn;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use super::{DirEntry, LocalFs, Metadata, OpenOptions, Vfs, VfsFile, normalize};
+use crate::quota::Usage;
+
+/// 上层中记录删除的标记文件前缀，`.wh.name` 表示下层的 `name` 及其中的内容已被删除
+const WHITEOUT: &str = ".wh.";
+
+fn absent(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::NotFound
+}
+
+fn whiteout(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}{}", WHITEOUT, name))
+}
+
+/// 复制到上层时临时文件名的序号
+static COPY_UP: AtomicU64 = AtomicU64::new(0);
+
+/// 叠加存储：下层只读，所有修改写入上层目录，读取时上层优先
+///
+/// 修改下层的文件前先把它复制到上层，删除下层的内容时在上层留下标记，
+/// 改名下层的目录会把整个目录复制到上层。下层本身不会被修改。
+#[derive(Debug)]
+pub struct OverlayFs {
+    lower: Arc<dyn Vfs>,
+    upper: LocalFs,
+    /// 与上层位于同一文件系统的工作目录，复制到上层的文件先写在这里再改名
+    work: PathBuf,
+}
+
+impl OverlayFs {
+    /// 用户的上层目录位于叠加目录下，以用户名 UTF-8 编码的十六进制命名，
+    /// 不同用户名不会在不区分大小写的文件系统上撞到同一个目录；不存在时创建
+    pub fn new(lower: Arc<dyn Vfs>, overlay: &Path, username: &str) -> Self {
+        let name: String = username.bytes().map(|b| format!("{:02x}", b)).collect();
+        let name = if name.is_empty() {
+            "_".to_string()
+        } else {
+            name
+        };
+        let upper = overlay.join(&name);
+        let work = overlay.join(format!("{}.work", name));
+        // 创建失败时仍可读取下层，写入会失败
+        for dir in [&upper, &work] {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                warn!("Failed to create overlay directory {:?}: {}", dir, e);
+            }
+        }
+        Self {
+            lower,
+            upper: LocalFs::new(upper),
+            work,
+        }
+    }
+
+    /// 客户端不能直接访问删除标记
+    fn check(path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path);
+        if path
+            .iter()
+            .any(|name| name.to_string_lossy().starts_with(WHITEOUT))
+        {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        Ok(path)
+    }
+
+    async fn in_upper(&self, path: &Path) -> bool {
+        self.upper.symlink_metadata(path).await.is_ok()
+    }
+
+    /// 路径自身或某个上级目录在上层留有删除标记，下层的内容不再可见
+    async fn hidden(&self, path: &Path) -> bool {
+        let mut prefix = PathBuf::new();
+        for name in path.iter() {
+            prefix.push(name);
+            if self.in_upper(&whiteout(&prefix)).await {
+                return true;
+            }
+        }
+        false
+    }
+
+    async fn lower_metadata(&self, path: &Path, follow: bool) -> io::Result<Metadata> {
+        if self.hidden(path).await {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        if follow {
+            self.lower.metadata(path).await
+        } else {
+            self.lower.symlink_metadata(path).await
+        }
+    }
+
+    async fn merged_metadata(&self, path: &Path, follow: bool) -> io::Result<Metadata> {
+        let upper = if follow {
+            self.upper.metadata(path).await
+        } else {
+            self.upper.symlink_metadata(path).await
+        };
+        match upper {
+            Err(e) if absent(&e) => self.lower_metadata(path, follow).await,
+            result => result,
+        }
+    }
+
+    /// 在上层创建目录及其上级目录，这些目录必须在合并后的视图中存在
+    async fn copy_up_dirs(&self, dir: &Path) -> io::Result<()> {
+        let mut prefix = PathBuf::new();
+        for name in dir.iter() {
+            prefix.push(name);
+            if self.in_upper(&prefix).await {
+                continue;
+            }
+            if !self.merged_metadata(&prefix, true).await?.is_dir() {
+                return Err(io::ErrorKind::NotADirectory.into());
+            }
+            self.upper.create_dir(&prefix).await?;
+        }
+        Ok(())
+    }
+
+    /// 把下层的文件复制到上层，之后的修改都在副本上进行
+    ///
+    /// 先复制到工作目录再改名，复制中断时上层不会留下不完整的文件遮住下层。
+    async fn copy_up(&self, path: &Path) -> io::Result<()> {
+        self.copy_up_dirs(path.parent().unwrap_or(Path::new("")))
+            .await?;
+        let target = self.upper.resolve(path)?;
+        let temp = self.work.join(format!(
+            "{}-{}",
+            std::process::id(),
+            COPY_UP.fetch_add(1, Ordering::Relaxed)
+        ));
+        let copied = async {
+            let mut reader = self.lower.open(path, OpenOptions::read()).await?;
+            let mut writer = fs::File::create(&temp).await?;
+            tokio::io::copy(&mut reader, &mut writer).await?;
+            writer.shutdown().await?;
+            fs::rename(&temp, &target).await
+        }
+        .await;
+        if copied.is_err() {
+            let _ = fs::remove_file(&temp).await;
+        }
+        copied
+    }
+
+    /// 把整个目录复制到上层，用于改名
+    async fn copy_up_tree(&self, dir: &Path) -> io::Result<()> {
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            self.copy_up_dirs(&dir).await?;
+            for entry in self.read_dir(&dir).await? {
+                let child = dir.join(&entry.name);
+                if entry.metadata.is_dir() {
+                    pending.push(child);
+                } else if !self.in_upper(&child).await {
+                    self.copy_up(&child).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 标记下层的路径已删除
+    async fn add_whiteout(&self, path: &Path) -> io::Result<()> {
+        self.copy_up_dirs(path.parent().unwrap_or(Path::new("")))
+            .await?;
+        let mut marker = self
+            .upper
+            .open(
+                &whiteout(path),
+                OpenOptions {
+                    write: true,
+                    create: true,
+                    truncate: true,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        marker.shutdown().await
+    }
+}
+
+#[async_trait]
+impl Vfs for OverlayFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.merged_metadata(&Self::check(path)?, true).await
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.merged_metadata(&Self::check(path)?, false).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let path = Self::check(path)?;
+        let mut entries = BTreeMap::new();
+        let mut whiteouts = HashSet::new();
+        let upper = match self.upper.read_dir(&path).await {
+            Ok(upper) => Some(upper),
+            Err(e) if absent(&e) => None,
+            Err(e) => return Err(e),
+        };
+        let found = upper.is_some();
+        for entry in upper.into_iter().flatten() {
+            match entry.name.strip_prefix(WHITEOUT) {
+                Some(name) => {
+                    whiteouts.insert(name.to_string());
+                }
+                None => {
+                    entries.insert(entry.name.clone(), entry);
+                }
+            }
+        }
+
+        let lower = if self.hidden(&path).await {
+            None
+        } else {
+            match self.lower.read_dir(&path).await {
+                Ok(lower) => Some(lower),
+                Err(e) if absent(&e) => None,
+                Err(e) => return Err(e),
+            }
+        };
+        if !found && lower.is_none() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        for entry in lower.into_iter().flatten() {
+            if !whiteouts.contains(&entry.name) && !entries.contains_key(&entry.name) {
+                entries.insert(entry.name.clone(), entry);
+            }
+        }
+        Ok(entries.into_values().collect())
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let path = Self::check(path)?;
+        if !options.is_write() {
+            return match self.upper.open(&path, options).await {
+                Err(e) if absent(&e) => {
+                    self.lower_metadata(&path, true).await?;
+                    self.lower.open(&path, options).await
+                }
+                result => result,
+            };
+        }
+
+        let mut options = options;
+        if !self.in_upper(&path).await {
+            match self.lower_metadata(&path, true).await {
+                Ok(metadata) if metadata.is_dir() => {
+                    return Err(io::ErrorKind::IsADirectory.into());
+                }
+                // 不截断时可能续传或随机写入，需要原来的内容
+                Ok(_) if !options.truncate => self.copy_up(&path).await?,
+                Ok(_) => {
+                    self.copy_up_dirs(path.parent().unwrap_or(Path::new("")))
+                        .await?;
+                    options.create = true;
+                }
+                Err(e) if absent(&e) && options.create => {
+                    self.copy_up_dirs(path.parent().unwrap_or(Path::new("")))
+                        .await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.upper.open(&path, options).await
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let path = Self::check(path)?;
+        if self.merged_metadata(&path, false).await.is_ok() {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        self.copy_up_dirs(path.parent().unwrap_or(Path::new("")))
+            .await?;
+        self.upper.create_dir(&path).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let path = Self::check(path)?;
+        if self.merged_metadata(&path, false).await?.is_dir() {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+        if self.in_upper(&path).await {
+            self.upper.remove_file(&path).await?;
+        }
+        if self.lower_metadata(&path, false).await.is_ok() {
+            self.add_whiteout(&path).await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let path = Self::check(path)?;
+        // 不允许删除根目录
+        if path.as_os_str().is_empty() {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        if !self.merged_metadata(&path, false).await?.is_dir() {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        if !self.read_dir(&path).await?.is_empty() {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+        if self.in_upper(&path).await {
+            // 合并后为空时上层目录中只剩删除标记
+            for entry in self.upper.read_dir(&path).await? {
+                self.upper.remove_file(&path.join(entry.name)).await?;
+            }
+            self.upper.remove_dir(&path).await?;
+        }
+        if self.lower_metadata(&path, false).await.is_ok() {
+            self.add_whiteout(&path).await?;
+        }
+        Ok(())
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let from = Self::check(from)?;
+        let to = Self::check(to)?;
+        if from.as_os_str().is_empty() {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let metadata = self.merged_metadata(&from, false).await?;
+        if self.merged_metadata(&to, false).await.is_ok() {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        let in_lower = self.lower_metadata(&from, false).await.is_ok();
+        if metadata.is_dir() {
+            self.copy_up_tree(&from).await?;
+        } else if !self.in_upper(&from).await {
+            self.copy_up(&from).await?;
+        }
+        self.copy_up_dirs(to.parent().unwrap_or(Path::new("")))
+            .await?;
+        self.upper.rename(&from, &to).await?;
+        if in_lower {
+            self.add_whiteout(&from).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let path = Self::check(path)?;
+        if !self.in_upper(&path).await {
+            if self.lower_metadata(&path, true).await?.is_dir() {
+                self.copy_up_dirs(&path).await?;
+            } else {
+                self.copy_up(&path).await?;
+            }
+        }
+        self.upper.set_permissions(&path, mode).await
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.metadata(path).await?;
+        Ok(Path::new("/").join(normalize(path)))
+    }
+
+    /// 只计算上层，即用户自己的修改
+    async fn usage(&self) -> io::Result<Usage> {
+        self.upper.usage().await
+    }
+
+    /// 修改都发生在上层，钩子拿到的是上层中的路径
+    fn display_path(&self, path: &Path) -> String {
+        self.upper.display_path(path)
+    }
+}
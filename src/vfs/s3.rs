@@ -0,0 +1,423 @@
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use futures_lite::StreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::buffered::{BufReader, BufWriter};
+use object_store::path::{Path as ObjectPath, PathPart};
+use object_store::{ObjectMeta, ObjectStore};
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use url::Url;
+
+use super::{DirEntry, FileKind, Metadata, OpenOptions, Vfs, VfsFile, normalize};
+use crate::quota::Usage;
+
+/// 对象存储没有目录，新建目录时在其中写入这个空对象，列目录时隐藏
+const DIR_MARKER: &str = ".keep";
+
+fn decode(part: &str) -> String {
+    percent_decode_str(part).decode_utf8_lossy().into_owned()
+}
+
+fn invalid_url(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "object storage only supports sequential uploads",
+    )
+}
+
+fn dir_metadata() -> Metadata {
+    Metadata {
+        kind: FileKind::Dir,
+        mode: Some(0o755),
+        ..Default::default()
+    }
+}
+
+fn file_metadata(meta: &ObjectMeta) -> Metadata {
+    Metadata {
+        kind: FileKind::File,
+        len: meta.size,
+        mode: Some(0o644),
+        modified: Some(meta.last_modified.into()),
+        ..Default::default()
+    }
+}
+
+/// S3 兼容的对象存储中的一个桶或桶内前缀，对象键中的 `/` 视为目录分隔符
+///
+/// 上传只能从头顺序写入，关闭时才生成对象，不支持续传和追加。
+#[derive(Debug)]
+pub struct S3Fs {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    /// 日志中显示的位置，例如 `s3://bucket`
+    location: String,
+}
+
+impl S3Fs {
+    /// 从 URL 连接：`s3://bucket/prefix` 使用 AWS，`http(s)://host:port/bucket/prefix` 使用自定义端点，
+    /// 例如本地的 MinIO。密钥可以写在 URL 的用户名和密码中，否则读取 `AWS_ACCESS_KEY_ID` 等环境变量。
+    pub fn from_url(url: &str) -> io::Result<Self> {
+        let url = Url::parse(url.trim()).map_err(|e| invalid_url(&e.to_string()))?;
+        let mut segments = url
+            .path_segments()
+            .into_iter()
+            .flatten()
+            .filter(|segment| !segment.is_empty())
+            .map(decode);
+        let mut builder = AmazonS3Builder::from_env();
+        let bucket = match url.scheme() {
+            "s3" => url.host_str().map(decode),
+            "http" | "https" => {
+                let host = url.host_str().ok_or_else(|| invalid_url("missing host"))?;
+                let endpoint = match url.port() {
+                    Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+                    None => format!("{}://{}", url.scheme(), host),
+                };
+                builder = builder.with_endpoint(endpoint).with_allow_http(true);
+                segments.next()
+            }
+            _ => {
+                return Err(invalid_url(
+                    "unsupported scheme, expected s3, http or https",
+                ));
+            }
+        }
+        .ok_or_else(|| invalid_url("missing bucket name"))?;
+        if !url.username().is_empty() {
+            builder = builder
+                .with_access_key_id(decode(url.username()))
+                .with_secret_access_key(decode(url.password().unwrap_or_default()));
+        }
+
+        let store = builder.with_bucket_name(&bucket).build()?;
+        Ok(Self {
+            store: Arc::new(store),
+            prefix: segments.map(PathPart::from).collect(),
+            location: format!("s3://{}", bucket),
+        })
+    }
+
+    /// 路径对应的对象键
+    fn key(&self, path: &Path) -> ObjectPath {
+        let path = normalize(path);
+        let parts = path
+            .components()
+            .map(|part| PathPart::from(part.as_os_str().to_string_lossy().into_owned()));
+        self.prefix.parts().chain(parts).collect()
+    }
+
+    /// 桶的根目录不能作为前缀传给列表接口
+    fn list_prefix<'a>(&self, key: &'a ObjectPath) -> Option<&'a ObjectPath> {
+        (!key.as_ref().is_empty()).then_some(key)
+    }
+
+    /// 前缀下有任何对象即视为目录存在
+    async fn is_dir(&self, key: &ObjectPath) -> io::Result<bool> {
+        let mut objects = self.store.list(self.list_prefix(key));
+        Ok(objects.next().await.transpose()?.is_some())
+    }
+
+    /// 对象的元数据，不存在时返回 `None`
+    async fn head(&self, key: &ObjectPath) -> io::Result<Option<ObjectMeta>> {
+        match self.store.head(key).await {
+            Ok(meta) => Ok(Some(meta)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn exists(&self, key: &ObjectPath) -> io::Result<bool> {
+        Ok(self.head(key).await?.is_some() || self.is_dir(key).await?)
+    }
+}
+
+#[async_trait]
+impl Vfs for S3Fs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let key = self.key(path);
+        if key == self.prefix {
+            return Ok(dir_metadata());
+        }
+        if let Some(meta) = self.head(&key).await? {
+            return Ok(file_metadata(&meta));
+        }
+        if self.is_dir(&key).await? {
+            Ok(dir_metadata())
+        } else {
+            Err(io::ErrorKind::NotFound.into())
+        }
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.metadata(path).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let key = self.key(path);
+        let listing = self
+            .store
+            .list_with_delimiter(self.list_prefix(&key))
+            .await?;
+        let mut entries: Vec<DirEntry> = listing
+            .common_prefixes
+            .iter()
+            .filter_map(|prefix| prefix.filename())
+            .map(|name| DirEntry {
+                name: decode(name),
+                metadata: dir_metadata(),
+            })
+            .collect();
+        for meta in &listing.objects {
+            match meta.location.filename() {
+                Some(DIR_MARKER) | None => {}
+                Some(name) => entries.push(DirEntry {
+                    name: decode(name),
+                    metadata: file_metadata(meta),
+                }),
+            }
+        }
+
+        // 空列表可能是空目录，也可能是不存在的路径或文件
+        if listing.objects.is_empty() && listing.common_prefixes.is_empty() && key != self.prefix {
+            return Err(match self.head(&key).await? {
+                Some(_) => io::ErrorKind::NotADirectory.into(),
+                None => io::ErrorKind::NotFound.into(),
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let key = self.key(path);
+        if options.is_write() {
+            if options.append || options.read {
+                return Err(unsupported());
+            }
+            if self.is_dir(&key).await? {
+                return Err(io::ErrorKind::IsADirectory.into());
+            }
+            if !options.create && self.head(&key).await?.is_none() {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+            return Ok(Box::new(ObjectFile::Writer {
+                writer: Mutex::new(BufWriter::new(self.store.clone(), key)),
+                written: 0,
+            }));
+        }
+
+        match self.head(&key).await? {
+            Some(meta) => Ok(Box::new(ObjectFile::Reader {
+                reader: Mutex::new(BufReader::new(self.store.clone(), &meta)),
+                metadata: file_metadata(&meta),
+            })),
+            None if self.is_dir(&key).await? => Err(io::ErrorKind::IsADirectory.into()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let key = self.key(path);
+        if key == self.prefix || self.exists(&key).await? {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        self.store
+            .put(&key.child(DIR_MARKER), Default::default())
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let key = self.key(path);
+        if self.head(&key).await?.is_none() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        self.store.delete(&key).await?;
+        Ok(())
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let key = self.key(path);
+        if key == self.prefix {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let marker = key.child(DIR_MARKER);
+        let mut objects = self.store.list(Some(&key));
+        let mut found = false;
+        while let Some(meta) = objects.next().await.transpose()? {
+            if meta.location != marker {
+                return Err(io::ErrorKind::DirectoryNotEmpty.into());
+            }
+            found = true;
+        }
+        if !found {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        self.store.delete(&marker).await?;
+        Ok(())
+    }
+
+    /// 对象存储没有改名操作，逐个复制后删除原对象
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (self.key(from), self.key(to));
+        if from == self.prefix || to.prefix_matches(&from) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        if self.exists(&to).await? {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        if self.head(&from).await?.is_some() {
+            self.store.rename(&from, &to).await?;
+            return Ok(());
+        }
+
+        let objects: Vec<ObjectMeta> = self.store.list(Some(&from)).try_collect().await?;
+        if objects.is_empty() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        for meta in objects {
+            let Some(rest) = meta.location.prefix_match(&from) else {
+                continue;
+            };
+            let target: ObjectPath = to.parts().chain(rest).collect();
+            self.store.rename(&meta.location, &target).await?;
+        }
+        Ok(())
+    }
+
+    /// 对象存储没有权限位，忽略
+    async fn set_permissions(&self, path: &Path, _mode: u32) -> io::Result<()> {
+        self.metadata(path).await.map(|_| ())
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.metadata(path).await?;
+        Ok(Path::new("/").join(normalize(path)))
+    }
+
+    async fn usage(&self) -> io::Result<Usage> {
+        let mut usage = Usage::default();
+        let mut objects = self.store.list(self.list_prefix(&self.prefix));
+        while let Some(meta) = objects.next().await.transpose()? {
+            if meta.location.filename() != Some(DIR_MARKER) {
+                usage.bytes += meta.size;
+                usage.files += 1;
+            }
+        }
+        Ok(usage)
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        format!("{}/{}", self.location, self.key(path))
+    }
+}
+
+/// 打开的对象，下载时按需读取范围，上传时缓冲后分段上传
+///
+/// 对象存储的读写器不是 `Sync`，用 `Mutex` 包装，读写时通过 `get_mut` 访问，不会真正加锁。
+#[derive(Debug)]
+enum ObjectFile {
+    Reader {
+        reader: Mutex<BufReader>,
+        metadata: Metadata,
+    },
+    Writer {
+        writer: Mutex<BufWriter>,
+        written: u64,
+    },
+}
+
+#[async_trait]
+impl VfsFile for ObjectFile {
+    async fn metadata(&self) -> io::Result<Metadata> {
+        match self {
+            Self::Reader { metadata, .. } => Ok(metadata.clone()),
+            Self::Writer { written, .. } => Ok(Metadata {
+                kind: FileKind::File,
+                len: *written,
+                mode: Some(0o644),
+                modified: Some(SystemTime::now()),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+impl AsyncRead for ObjectFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Reader { reader, .. } => Pin::new(reader.get_mut().unwrap()).poll_read(cx, buf),
+            Self::Writer { .. } => Poll::Ready(Err(unsupported())),
+        }
+    }
+}
+
+impl AsyncWrite for ObjectFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Reader { .. } => Poll::Ready(Err(io::ErrorKind::PermissionDenied.into())),
+            Self::Writer { writer, written } => {
+                let result = Pin::new(writer.get_mut().unwrap()).poll_write(cx, buf);
+                if let Poll::Ready(Ok(n)) = result {
+                    *written += n as u64;
+                }
+                result
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Reader { .. } => Poll::Ready(Ok(())),
+            Self::Writer { writer, .. } => Pin::new(writer.get_mut().unwrap()).poll_flush(cx),
+        }
+    }
+
+    /// 上传在关闭时完成
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Reader { .. } => Poll::Ready(Ok(())),
+            Self::Writer { writer, .. } => Pin::new(writer.get_mut().unwrap()).poll_shutdown(cx),
+        }
+    }
+}
+
+impl AsyncSeek for ObjectFile {
+    /// 上传时只允许定位到已写入的末尾，即顺序写入
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.get_mut() {
+            Self::Reader { reader, .. } => Pin::new(reader.get_mut().unwrap()).start_seek(position),
+            Self::Writer { written, .. } => match position {
+                SeekFrom::Start(offset) if offset == *written => Ok(()),
+                SeekFrom::Current(0) | SeekFrom::End(0) => Ok(()),
+                _ => Err(unsupported()),
+            },
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.get_mut() {
+            Self::Reader { reader, .. } => Pin::new(reader.get_mut().unwrap()).poll_complete(cx),
+            Self::Writer { written, .. } => Poll::Ready(Ok(*written)),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use tokio::fs;
+
+use super::{DirEntry, LocalFs, Metadata, OpenOptions, Vfs, VfsFile, normalize};
+use crate::quota::Usage;
+
+#[derive(Debug)]
+enum Target {
+    File(PathBuf),
+    Dir(LocalFs),
+}
+
+#[derive(Debug)]
+struct Selected {
+    /// 在根目录下显示的名称
+    name: String,
+    target: Target,
+}
+
+/// 路径所在的选中项
+enum Route<'a> {
+    Root,
+    File(&'a Path),
+    /// 选中的目录及其中的路径
+    Dir(&'a LocalFs, &'a str, PathBuf),
+}
+
+/// 把选中的文件和目录平铺在根目录下，不暴露它们所在的目录
+///
+/// 根目录和选中的文件只读，选中的目录内可以按权限正常修改。
+#[derive(Debug)]
+pub struct SelectionFs {
+    entries: Vec<Selected>,
+}
+
+impl SelectionFs {
+    /// 重名的项加上序号区分，例如 `report (2).pdf`
+    pub fn new(paths: &[PathBuf]) -> Self {
+        let mut taken = HashSet::new();
+        let entries = paths
+            .iter()
+            .map(|path| {
+                let name = unique_name(path, &mut taken);
+                let target = if path.is_dir() {
+                    Target::Dir(LocalFs::new(path.clone()))
+                } else {
+                    Target::File(path.clone())
+                };
+                Selected { name, target }
+            })
+            .collect();
+        Self { entries }
+    }
+
+    fn route(&self, path: &Path) -> io::Result<Route<'_>> {
+        let path = normalize(path);
+        let mut components = path.components();
+        let Some(name) = components.next() else {
+            return Ok(Route::Root);
+        };
+        let rest = components.as_path();
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| name.as_os_str() == entry.name.as_str())
+            .ok_or(io::ErrorKind::NotFound)?;
+        match &entry.target {
+            Target::File(file) if rest.as_os_str().is_empty() => Ok(Route::File(file)),
+            Target::File(_) => Err(io::ErrorKind::NotFound.into()),
+            Target::Dir(dir) => Ok(Route::Dir(dir, &entry.name, Path::new("/").join(rest))),
+        }
+    }
+
+    /// 只有选中目录内的内容可以修改
+    fn writable(&self, path: &Path) -> io::Result<(&LocalFs, PathBuf)> {
+        match self.route(path) {
+            Ok(Route::Dir(dir, _, inner)) if inner != Path::new("/") => Ok((dir, inner)),
+            _ => Err(io::ErrorKind::PermissionDenied.into()),
+        }
+    }
+}
+
+fn unique_name(path: &Path, taken: &mut HashSet<String>) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    let mut unique = name.clone();
+    let mut n = 2;
+    while !taken.insert(unique.clone()) {
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy());
+        unique = match (stem, path.extension()) {
+            (Some(stem), Some(extension)) => {
+                format!("{} ({}).{}", stem, n, extension.to_string_lossy())
+            }
+            _ => format!("{} ({})", name, n),
+        };
+        n += 1;
+    }
+    unique
+}
+
+#[async_trait]
+impl Vfs for SelectionFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.route(path)? {
+            Route::Root => Ok(Metadata::virtual_dir()),
+            Route::File(file) => fs::metadata(file).await.map(Metadata::from),
+            Route::Dir(dir, _, inner) => dir.metadata(&inner).await,
+        }
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.route(path)? {
+            Route::Root => Ok(Metadata::virtual_dir()),
+            Route::File(file) => fs::symlink_metadata(file).await.map(Metadata::from),
+            Route::Dir(dir, _, inner) => dir.symlink_metadata(&inner).await,
+        }
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        match self.route(path)? {
+            Route::Root => {
+                let mut entries = Vec::with_capacity(self.entries.len());
+                for entry in &self.entries {
+                    let metadata = match &entry.target {
+                        Target::File(file) => fs::metadata(file).await.map(Metadata::from),
+                        Target::Dir(dir) => dir.metadata(Path::new("/")).await,
+                    };
+                    // 已被移动或删除的项不再显示
+                    if let Ok(metadata) = metadata {
+                        entries.push(DirEntry {
+                            name: entry.name.clone(),
+                            metadata,
+                        });
+                    }
+                }
+                Ok(entries)
+            }
+            Route::File(_) => Err(io::ErrorKind::NotADirectory.into()),
+            Route::Dir(dir, _, inner) => dir.read_dir(&inner).await,
+        }
+    }
+
+    async fn open(&self, path: &Path, options: OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        if options.is_write() {
+            let (dir, inner) = self.writable(path)?;
+            return dir.open(&inner, options).await;
+        }
+        match self.route(path)? {
+            Route::Root => Err(io::ErrorKind::IsADirectory.into()),
+            Route::File(file) => Ok(Box::new(fs::File::open(file).await?)),
+            Route::Dir(dir, _, inner) => dir.open(&inner, options).await,
+        }
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let (dir, inner) = self.writable(path)?;
+        dir.create_dir(&inner).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let (dir, inner) = self.writable(path)?;
+        dir.remove_file(&inner).await
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let (dir, inner) = self.writable(path)?;
+        dir.remove_dir(&inner).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from_dir, from) = self.writable(from)?;
+        let (to_dir, to) = self.writable(to)?;
+        if !std::ptr::eq(from_dir, to_dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot rename across shared folders",
+            ));
+        }
+        from_dir.rename(&from, &to).await
+    }
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let (dir, inner) = self.writable(path)?;
+        dir.set_permissions(&inner, mode).await
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let normalized = normalize(path);
+        match self.route(path)? {
+            Route::Root | Route::File(_) => Ok(Path::new("/").join(normalized)),
+            Route::Dir(dir, name, inner) => {
+                let mut result = Path::new("/").join(name);
+                let canonical = dir.canonicalize(&inner).await?;
+                let relative = canonical.strip_prefix("/").unwrap_or(&canonical);
+                if !relative.as_os_str().is_empty() {
+                    result.push(relative);
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    async fn usage(&self) -> io::Result<Usage> {
+        let mut total = Usage::default();
+        for entry in &self.entries {
+            match &entry.target {
+                Target::File(file) => {
+                    if let Ok(metadata) = fs::metadata(file).await {
+                        total.bytes += metadata.len();
+                        total.files += 1;
+                    }
+                }
+                Target::Dir(dir) => {
+                    let usage = dir.usage().await?;
+                    total.bytes += usage.bytes;
+                    total.files += usage.files;
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        match self.route(path) {
+            Ok(Route::File(file)) => file.to_string_lossy().into_owned(),
+            Ok(Route::Dir(dir, _, inner)) => dir.display_path(&inner),
+            Ok(Route::Root) | Err(_) => Path::new("/").join(normalize(path)).display().to_string(),
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use std::io::{self, SeekFrom};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use bytes::{Buf, Bytes};
+use dav_server::davpath::DavPath;
+use dav_server::fs::{
+    DavDirEntry, DavFile, DavFileSystem, DavMetaData, FsError, FsFuture, FsResult, FsStream,
+    OpenOptions, ReadDirMeta,
+};
+use dav_server::localfs::LocalFs;
+use log::warn;
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use russh_sftp::protocol::StatusCode;
+
+use crate::accounts::{Access, Account};
+use crate::audit::{TransferSource, TransferTracker};
+use crate::hooks::{Event, EventKind};
+use crate::permissions::{PathRules, Permission};
+use crate::policy::{Decision, UploadCheck};
+use crate::quota::{self, Quota};
+use crate::server::Services;
+use crate::sftp::utils::path_resolver::PathResolver;
+use crate::throttle::{Direction, TransferThrottle};
+
+/// 策略脚本改写后的路径需要重新编码才能构造 `DavPath`
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?');
+
+/// 与 `PathResolver` 相同的根目录限制，`home` 须已解析符号链接；
+/// 尚不存在的路径只要上级目录在主目录内就交给 `LocalFs` 处理
+fn confine(home: &Path, path: &DavPath) -> FsResult<()> {
+    match PathResolver::new(home.to_path_buf()).resolve_path(&path.to_string()) {
+        Ok(_) => Ok(()),
+        Err(StatusCode::PermissionDenied) => Err(FsError::Forbidden),
+        Err(_) => Err(FsError::NotFound),
+    }
+}
+
+/// 包装 `LocalFs`，与 SFTP 一样限制在主目录内，并施加限速、配额、策略和传输日志
+#[derive(Clone)]
+pub struct DavFs {
+    inner: Box<LocalFs>,
+    home: PathBuf,
+    source: TransferSource,
+    quota: Quota,
+    access: Access,
+    permissions: PathRules,
+    services: Services,
+}
+
+impl DavFs {
+    /// 主目录先解析符号链接，之后解析出的路径才能与它比较
+    pub fn new(
+        home: &Path,
+        account: &Account,
+        peer: SocketAddr,
+        services: &Services,
+    ) -> io::Result<Self> {
+        let home = home.canonicalize()?;
+        Ok(Self {
+            inner: LocalFs::new(&home, false, false, false),
+            home,
+            source: TransferSource {
+                protocol: "webdav",
+                username: account.username.clone(),
+                anonymous: account.anonymous,
+                remote_host: peer.ip().to_string(),
+            },
+            quota: account.quota(),
+            access: account.access,
+            permissions: account.permissions.clone(),
+            services: services.clone(),
+        })
+    }
+
+    fn confine(&self, path: &DavPath) -> FsResult<()> {
+        confine(&self.home, path)
+    }
+
+    /// 检查共享模式和账户在该路径上的权限，以及访客账户是否已失效
+    fn check(&self, path: &DavPath, permission: Permission) -> FsResult<()> {
+        let granted = self.permissions.resolve(self.access, path.as_rel_ospath());
+        if self
+            .services
+            .permits(&self.source.username, granted, permission)
+        {
+            Ok(())
+        } else {
+            warn!(
+                "{}: {} not permitted on {}",
+                self.source.username, permission, path
+            );
+            Err(FsError::Forbidden)
+        }
+    }
+
+    fn real_path(&self, path: &DavPath) -> PathBuf {
+        self.home.join(path.as_rel_ospath())
+    }
+
+    fn apply_policy(&self, path: &DavPath, decision: Decision) -> FsResult<DavPath> {
+        match decision {
+            Decision::Allow => Ok(path.clone()),
+            Decision::Deny => {
+                warn!("{}: {} denied by policy", self.source.username, path);
+                Err(FsError::Forbidden)
+            }
+            Decision::Rewrite(rewritten) => {
+                DavPath::new(&utf8_percent_encode(&rewritten, PATH).to_string())
+                    .map_err(|_| FsError::Forbidden)
+            }
+        }
+    }
+
+    /// 根据配额计算本次最多可写入的字节数，`None` 表示不限制
+    async fn upload_allowance(&self, path: &DavPath, truncate: bool) -> FsResult<Option<u64>> {
+        if self.quota.is_unlimited() {
+            return Ok(None);
+        }
+
+        let mut usage = quota::usage(self.home.clone()).await.map_err(|e| {
+            warn!("Failed to compute quota usage: {}", e);
+            FsError::GeneralFailure
+        })?;
+        match self.inner.metadata(path).await {
+            Ok(metadata) => {
+                if truncate {
+                    usage.bytes = usage.bytes.saturating_sub(metadata.len());
+                }
+            }
+            Err(_) => {
+                if !self.quota.allows_new_file(&usage) {
+                    warn!(
+                        "{}: file quota exceeded, rejecting {}",
+                        self.source.username, path
+                    );
+                    return Err(FsError::InsufficientStorage);
+                }
+            }
+        }
+        Ok(self.quota.remaining_bytes(&usage))
+    }
+}
+
+impl DavFileSystem for DavFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        Box::pin(async move {
+            let write = options.write
+                || options.append
+                || options.create
+                || options.create_new
+                || options.truncate;
+            let requested = path.to_string();
+            let policy = &self.services.policy;
+            let decision = if write {
+                policy.on_upload(&requested, 0)
+            } else {
+                policy.on_download(&requested)
+            };
+            // 上传过程中再次询问时与打开时裁决的路径比较
+            let target = match &decision {
+                Decision::Rewrite(rewritten) => rewritten.clone(),
+                _ => requested.clone(),
+            };
+            let path = self.apply_policy(path, decision)?;
+            self.confine(&path)?;
+            let existing = self.inner.metadata(&path).await.ok();
+            let permission = if !write {
+                Permission::Download
+            } else if existing.is_some() {
+                Permission::Overwrite
+            } else {
+                Permission::Upload
+            };
+            self.check(&path, permission)?;
+
+            let allowance = if write {
+                self.upload_allowance(&path, options.truncate).await?
+            } else {
+                None
+            };
+            // 超出配额时恢复到的长度，`None` 表示文件由本次上传新建
+            let original = match existing {
+                Some(_) if options.truncate => Some(0),
+                Some(metadata) => Some(metadata.len()),
+                None => None,
+            };
+            let mut file = self.inner.open(&path, options).await?;
+            let len = if write {
+                0
+            } else {
+                file.metadata().await?.len()
+            };
+
+            let direction = if write {
+                Direction::Upload
+            } else {
+                Direction::Download
+            };
+            let real_path = self.real_path(&path);
+            let tracker = TransferTracker::new(
+                &self.services,
+                self.source.clone(),
+                real_path.to_string_lossy().into_owned(),
+                direction,
+            );
+            let upload = write
+                .then(|| UploadCheck::new(policy.clone(), requested, PathBuf::from(target), 0));
+            Ok(Box::new(TrackedFile {
+                inner: file,
+                tracker,
+                transfer: self.services.throttle.session(&self.source.username),
+                direction,
+                len,
+                position: 0,
+                allowance,
+                written: 0,
+                original,
+                real_path,
+                upload,
+            }) as Box<dyn DavFile>)
+        })
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        Box::pin(async move {
+            self.check(path, Permission::List)?;
+            self.confine(path)?;
+            self.inner.read_dir(path, meta).await
+        })
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        Box::pin(async move {
+            self.confine(path)?;
+            self.inner.metadata(path).await
+        })
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        Box::pin(async move {
+            self.confine(path)?;
+            self.inner.symlink_metadata(path).await
+        })
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.check(path, Permission::Mkdir)?;
+            self.confine(path)?;
+            self.inner.create_dir(path).await
+        })
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.check(path, Permission::Rmdir)?;
+            self.confine(path)?;
+            self.inner.remove_dir(path).await
+        })
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.check(path, Permission::Delete)?;
+            self.confine(path)?;
+            self.inner.remove_file(path).await?;
+            self.services.hooks.fire(
+                Event::new(EventKind::Delete, self.source.clone())
+                    .with_path(self.real_path(path).to_string_lossy()),
+            );
+            Ok(())
+        })
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.check(from, Permission::Rename)?;
+            self.check(to, Permission::Rename)?;
+            self.confine(from)?;
+            self.confine(to)?;
+            self.inner.rename(from, to).await
+        })
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.check(from, Permission::Download)?;
+            self.check(to, Permission::Upload)?;
+            self.confine(from)?;
+            self.confine(to)?;
+            self.inner.copy(from, to).await
+        })
+    }
+}
+
+/// 统计读写字节数的文件句柄：下载读到结尾、上传 flush 时视为传输完成
+#[derive(Debug)]
+struct TrackedFile {
+    inner: Box<dyn DavFile>,
+    tracker: TransferTracker,
+    transfer: TransferThrottle,
+    direction: Direction,
+    len: u64,
+    position: u64,
+    allowance: Option<u64>,
+    written: u64,
+    original: Option<u64>,
+    real_path: PathBuf,
+    /// 上传过程中再次询问策略脚本
+    upload: Option<UploadCheck>,
+}
+
+impl TrackedFile {
+    /// 丢弃本次写入的内容：已有文件截回原来的长度，新建的文件删除
+    async fn discard(&self) -> io::Result<()> {
+        match self.original {
+            Some(len) => {
+                tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&self.real_path)
+                    .await?
+                    .set_len(len)
+                    .await
+            }
+            None => tokio::fs::remove_file(&self.real_path).await,
+        }
+    }
+
+    /// 上传被策略拒绝时丢弃已写入的内容
+    async fn deny(&self) -> FsError {
+        if let Err(e) = self.discard().await {
+            warn!("Failed to discard partial upload: {}", e);
+        }
+        FsError::Forbidden
+    }
+}
+
+impl DavFile for TrackedFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.inner.metadata()
+    }
+
+    fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        let bytes = buf.copy_to_bytes(buf.remaining());
+        self.write_bytes(bytes)
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        Box::pin(async move {
+            let n = buf.len() as u64;
+            self.written += n;
+            if self
+                .allowance
+                .is_some_and(|allowance| self.written > allowance)
+            {
+                warn!("Byte quota exceeded while uploading {:?}", self.real_path);
+                if let Err(e) = self.discard().await {
+                    warn!("Failed to discard partial upload: {}", e);
+                }
+                return Err(FsError::InsufficientStorage);
+            }
+            let written = self.written;
+            if self
+                .upload
+                .as_mut()
+                .is_some_and(|upload| !upload.wrote(written))
+            {
+                return Err(self.deny().await);
+            }
+
+            self.transfer.consume(Direction::Upload, n).await;
+            self.inner.write_bytes(buf).await?;
+            self.tracker.add(n);
+            Ok(())
+        })
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        Box::pin(async move {
+            let bytes = self.inner.read_bytes(count).await?;
+            let n = bytes.len() as u64;
+            self.transfer.consume(Direction::Download, n).await;
+            self.tracker.add(n);
+            self.position += n;
+            if n == 0 || self.position >= self.len {
+                self.tracker.set_complete();
+            }
+            Ok(bytes)
+        })
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        Box::pin(async move {
+            self.position = self.inner.seek(pos).await?;
+            if self.direction == Direction::Download {
+                self.tracker.read_from(self.position);
+            }
+            Ok(self.position)
+        })
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        Box::pin(async move {
+            // 上传结束时按最终大小再询问一次
+            if self.upload.as_mut().is_some_and(|upload| !upload.finish()) {
+                return Err(self.deny().await);
+            }
+            self.inner.flush().await?;
+            if self.direction == Direction::Upload {
+                self.tracker.set_complete();
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn confine_rejects_symlinks_out_of_home() {
+        let base = std::env::temp_dir().join(format!("webdav-confine-{}", std::process::id()));
+        let home = base.join("home");
+        let outside = base.join("outside");
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret"), "secret").unwrap();
+        std::fs::write(home.join("file"), "file").unwrap();
+        std::os::unix::fs::symlink(&outside, home.join("link")).unwrap();
+        // 通过符号链接选择的共享目录须先解析，否则已有的文件也会被拒绝
+        let linked = base.join("home-link");
+        std::os::unix::fs::symlink(&home, &linked).unwrap();
+        let file = DavPath::new("/file").unwrap();
+        assert_eq!(confine(&linked, &file), Err(FsError::Forbidden));
+        let home = linked.canonicalize().unwrap();
+
+        let path = |path: &str| DavPath::new(path).unwrap();
+        assert_eq!(confine(&home, &path("/")), Ok(()));
+        assert_eq!(confine(&home, &path("/file")), Ok(()));
+        assert_eq!(confine(&home, &path("/new")), Ok(()));
+        assert_eq!(
+            confine(&home, &path("/missing/new")),
+            Err(FsError::NotFound)
+        );
+        for escape in ["/link", "/link/secret", "/link/new"] {
+            assert_eq!(
+                confine(&home, &path(escape)),
+                Err(FsError::Forbidden),
+                "{}",
+                escape
+            );
+        }
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
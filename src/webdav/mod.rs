@@ -0,0 +1,89 @@
+mod fs;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use axum::Extension;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Request, State};
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use dav_server::DavHandler;
+use dav_server::ls::DavLockSystem;
+use dav_server::memls::MemLs;
+use log::warn;
+
+use crate::accounts::Account;
+use crate::http::{HttpState, basic_auth};
+
+pub use fs::DavFs;
+
+/// WebDAV 服务器状态，认证与 HTTP 协议共用
+#[derive(Debug)]
+pub struct WebDavState {
+    pub http: Arc<HttpState>,
+    /// 每个账户一个锁表，不同主目录下的同名路径互不冲突
+    locks: Mutex<HashMap<String, Box<MemLs>>>,
+}
+
+impl WebDavState {
+    pub fn new(http: Arc<HttpState>) -> Self {
+        Self {
+            http,
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn locks(&self, username: &str) -> Box<dyn DavLockSystem> {
+        let mut locks = self.locks.lock().unwrap();
+        locks
+            .entry(username.to_string())
+            .or_insert_with(MemLs::new)
+            .clone()
+    }
+}
+
+pub fn router(state: Arc<WebDavState>) -> Router {
+    Router::new()
+        .fallback(handle)
+        .layer(middleware::from_fn_with_state(
+            state.http.clone(),
+            basic_auth,
+        ))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state)
+}
+
+/// 每个请求按账户主目录创建文件系统，支持 class 1 和 class 2（锁）
+async fn handle(
+    State(state): State<Arc<WebDavState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(account): Extension<Account>,
+    request: Request,
+) -> Response {
+    let home = match account.home_dir(&state.http.root) {
+        Ok(home) => home,
+        Err(e) => {
+            warn!("Failed to prepare home directory: {}", e);
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    };
+
+    let fs = match DavFs::new(&home, &account, peer, &state.http.services) {
+        Ok(fs) => fs,
+        Err(e) => {
+            warn!("Failed to resolve home directory {:?}: {}", home, e);
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    };
+    let handler = DavHandler::builder()
+        .filesystem(Box::new(fs))
+        .locksystem(state.locks(&account.username))
+        .principal(account.username.clone())
+        .autoindex(true)
+        .build_handler();
+    handler.handle(request).await.map(Body::new)
+}